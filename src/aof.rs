@@ -0,0 +1,1028 @@
+use std::{
+    fmt,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use dashmap::DashMap;
+use resp_protocol::{ArrayBuilder, BulkString, RespType};
+use tklog::{error, info};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use crate::{
+    commands::{self, ClientState},
+    db::{KeyValue, RdbFile, RdbParser, RedisValue, RDB_VERSION},
+    error::RedisError,
+    persistence,
+    resp::RespDecoder,
+    server::{Server, ServerOpt},
+};
+
+// Append-only file: every write command that changed the dataset is appended in RESP form,
+// and replaying it at startup rebuilds the dataset.
+//
+// Like Redis 7 the AOF is split into parts kept in `appenddirname` and listed by a manifest:
+// one base file holding the dataset as of the last rewrite (written as an RDB, so no
+// commands are needed for every value type) and the incremental files with the commands
+// that came after it. BGREWRITEAOF switches writes to a new incremental file, writes a new
+// base from a copy of the keyspace in the background, then drops the parts it replaces.
+//
+// Relative expiry times are logged as absolute ones (PEXPIREAT, SET ... PXAT) so a replay
+// doesn't extend ttls by however long the server was down.
+
+pub const DEFAULT_APPENDFILENAME: &str = "appendonly.aof";
+pub const DEFAULT_APPENDDIRNAME: &str = "appendonlydir";
+// rewrite once the AOF has grown this many percent over its size after the last rewrite,
+// 0 disables automatic rewrites
+pub const DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE: u64 = 100;
+// but never while it's smaller than this
+pub const DEFAULT_AUTO_AOF_REWRITE_MIN_SIZE: u64 = 64 * 1024 * 1024;
+// elements per command when a collection is written out as commands
+const ITEMS_PER_CMD: usize = 64;
+
+// when appended data is fsynced
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppendFsync {
+    // after every write command
+    Always,
+    // once per second from a background task
+    EverySec,
+    // left to the operating system
+    No,
+}
+
+impl AppendFsync {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Some(AppendFsync::Always),
+            "everysec" => Some(AppendFsync::EverySec),
+            "no" => Some(AppendFsync::No),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AppendFsync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AppendFsync::Always => "always",
+            AppendFsync::EverySec => "everysec",
+            AppendFsync::No => "no",
+        })
+    }
+}
+
+// a file listed in the manifest
+#[derive(Debug, Clone, PartialEq)]
+struct AofPart {
+    name: String,
+    seq: u64,
+}
+
+// The manifest, one line per file:
+//   file appendonly.aof.2.base.rdb seq 2 type b
+//   file appendonly.aof.3.incr.aof seq 3 type i
+// Files of type h (history) are left over from a rewrite and not loaded.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Manifest {
+    base: Option<AofPart>,
+    incrs: Vec<AofPart>,
+    // the last sequence numbers handed out
+    base_seq: u64,
+    incr_seq: u64,
+}
+
+impl Manifest {
+    fn parse(text: &str) -> Result<Self> {
+        let mut manifest = Manifest::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() % 2 != 0 {
+                bail!("Invalid AOF manifest file format at line {}", n + 1);
+            }
+            let (mut name, mut seq, mut kind) = (None, None, None);
+            for pair in words.chunks(2) {
+                match pair[0] {
+                    "file" => name = Some(pair[1].to_string()),
+                    "seq" => seq = pair[1].parse::<u64>().ok(),
+                    "type" => kind = Some(pair[1]),
+                    // unknown keys may come from a newer version
+                    _ => {}
+                }
+            }
+            let (Some(name), Some(seq), Some(kind)) = (name, seq, kind) else {
+                bail!("Invalid AOF manifest file format at line {}", n + 1);
+            };
+            let part = AofPart { name, seq };
+            match kind {
+                "b" => {
+                    if manifest.base.is_some() {
+                        bail!("Found duplicate base file information");
+                    }
+                    manifest.base_seq = seq;
+                    manifest.base = Some(part);
+                }
+                "i" => {
+                    if seq <= manifest.incr_seq {
+                        bail!("Found a non-monotonic sequence number");
+                    }
+                    manifest.incr_seq = seq;
+                    manifest.incrs.push(part);
+                }
+                "h" => {}
+                _ => bail!("Unknown AOF file type {}", kind),
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+// the files a manifest lists, in the order they are loaded
+pub fn manifest_files(text: &str) -> Result<Vec<String>> {
+    let manifest = Manifest::parse(text)?;
+    Ok(manifest
+        .base
+        .into_iter()
+        .chain(manifest.incrs)
+        .map(|part| part.name)
+        .collect())
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(base) = &self.base {
+            writeln!(f, "file {} seq {} type b", base.name, base.seq)?;
+        }
+        for incr in &self.incrs {
+            writeln!(f, "file {} seq {} type i", incr.name, incr.seq)?;
+        }
+        Ok(())
+    }
+}
+
+// the open file commands are appended to
+#[derive(Debug)]
+struct AofFile {
+    file: File,
+    // the database of the last logged command, a SELECT is logged when it changes
+    selected_db: Option<u64>,
+    // data written since the last fsync
+    unsynced: bool,
+    // bytes in the file
+    len: u64,
+    // commands not written yet because writing failed, written before anything else
+    pending: Vec<u8>,
+}
+
+impl AofFile {
+    // Write what is pending and return how many bytes were written. When writing fails the
+    // part that reached the file is dropped from `pending`, so a retry never appends a
+    // command twice.
+    async fn write_pending(&mut self) -> Result<u64> {
+        if self.pending.is_empty() {
+            return Ok(0);
+        }
+        let result = match self.file.write_all(&self.pending).await {
+            Ok(()) => self.file.flush().await,
+            Err(e) => Err(e),
+        };
+        let written = match result {
+            Ok(()) => self.pending.len() as u64,
+            Err(_) => match self.file.metadata().await {
+                Ok(meta) => meta
+                    .len()
+                    .saturating_sub(self.len)
+                    .min(self.pending.len() as u64),
+                Err(_) => 0,
+            },
+        };
+        self.pending.drain(..written as usize);
+        self.len += written;
+        self.unsynced |= written > 0;
+        result.context("error writing to the AOF")?;
+        Ok(written)
+    }
+}
+
+#[derive(Debug)]
+pub struct Aof {
+    // appendonly, changed by CONFIG SET appendonly
+    enabled: AtomicBool,
+    fsync: RwLock<AppendFsync>,
+    file: Mutex<Option<AofFile>>,
+    manifest: Mutex<Manifest>,
+    // write commands run one at a time, so they are logged in the order they were applied
+    pub write_lock: Mutex<()>,
+    rewrite_in_progress: AtomicBool,
+    last_rewrite_ok: AtomicBool,
+    // duration of the last rewrite in seconds, -1 before the first one
+    last_rewrite_secs: AtomicI64,
+    // unix time in ms when the running rewrite started
+    rewrite_started: AtomicU64,
+    // size of all the parts after the last rewrite or load, and now
+    base_size: AtomicU64,
+    current_size: AtomicU64,
+    auto_rewrite_perc: AtomicU64,
+    auto_rewrite_min_size: AtomicU64,
+    // aof-load-truncated: load an AOF whose last command was cut short instead of refusing to start
+    load_truncated: AtomicBool,
+    // why the last write or fsync failed, write commands are refused until one works again
+    write_error: RwLock<Option<String>>,
+}
+
+impl Aof {
+    pub fn new(conf: &ServerOpt) -> Self {
+        Aof {
+            enabled: AtomicBool::new(conf.appendonly),
+            fsync: RwLock::new(conf.appendfsync),
+            file: Mutex::new(None),
+            manifest: Mutex::new(Manifest::default()),
+            write_lock: Mutex::new(()),
+            rewrite_in_progress: AtomicBool::new(false),
+            last_rewrite_ok: AtomicBool::new(true),
+            last_rewrite_secs: AtomicI64::new(-1),
+            rewrite_started: AtomicU64::new(0),
+            base_size: AtomicU64::new(0),
+            current_size: AtomicU64::new(0),
+            auto_rewrite_perc: AtomicU64::new(conf.auto_aof_rewrite_percentage),
+            auto_rewrite_min_size: AtomicU64::new(conf.auto_aof_rewrite_min_size),
+            load_truncated: AtomicBool::new(conf.aof_load_truncated),
+            write_error: RwLock::new(None),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn fsync(&self) -> AppendFsync {
+        *self.fsync.read().expect("appendfsync lock poisoned")
+    }
+
+    pub fn set_fsync(&self, fsync: AppendFsync) {
+        *self.fsync.write().expect("appendfsync lock poisoned") = fsync;
+    }
+
+    pub fn auto_rewrite_perc(&self) -> u64 {
+        self.auto_rewrite_perc.load(Ordering::Relaxed)
+    }
+
+    pub fn set_auto_rewrite_perc(&self, perc: u64) {
+        self.auto_rewrite_perc.store(perc, Ordering::Relaxed);
+    }
+
+    pub fn auto_rewrite_min_size(&self) -> u64 {
+        self.auto_rewrite_min_size.load(Ordering::Relaxed)
+    }
+
+    pub fn set_auto_rewrite_min_size(&self, size: u64) {
+        self.auto_rewrite_min_size.store(size, Ordering::Relaxed);
+    }
+
+    pub fn load_truncated(&self) -> bool {
+        self.load_truncated.load(Ordering::Relaxed)
+    }
+
+    pub fn set_load_truncated(&self, enabled: bool) {
+        self.load_truncated.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn write_error(&self) -> Option<String> {
+        self.write_error
+            .read()
+            .expect("aof write error lock poisoned")
+            .clone()
+    }
+
+    fn set_write_error(&self, error: Option<String>) {
+        *self
+            .write_error
+            .write()
+            .expect("aof write error lock poisoned") = error;
+    }
+
+    // the growth that calls for an automatic rewrite, in percent
+    fn due_rewrite(&self) -> Option<u64> {
+        let perc = self.auto_rewrite_perc();
+        let current = self.current_size.load(Ordering::Relaxed);
+        if !self.enabled()
+            || perc == 0
+            || self.rewrite_in_progress.load(Ordering::Relaxed)
+            || current < self.auto_rewrite_min_size()
+        {
+            return None;
+        }
+        let base = self.base_size.load(Ordering::Relaxed).max(1);
+        let growth = current.saturating_sub(base) * 100 / base;
+        (growth >= perc).then_some(growth)
+    }
+
+    pub fn to_info(&self, now_millis: u64) -> DashMap<String, String> {
+        let section = DashMap::new();
+        let in_progress = self.rewrite_in_progress.load(Ordering::Relaxed);
+        section.insert(
+            "aof_enabled".to_string(),
+            (self.enabled() as u8).to_string(),
+        );
+        section.insert(
+            "aof_rewrite_in_progress".to_string(),
+            (in_progress as u8).to_string(),
+        );
+        section.insert(
+            "aof_last_rewrite_time_sec".to_string(),
+            self.last_rewrite_secs.load(Ordering::Relaxed).to_string(),
+        );
+        let current = if in_progress {
+            (now_millis.saturating_sub(self.rewrite_started.load(Ordering::Relaxed)) / 1000) as i64
+        } else {
+            -1
+        };
+        section.insert(
+            "aof_current_rewrite_time_sec".to_string(),
+            current.to_string(),
+        );
+        section.insert(
+            "aof_last_bgrewrite_status".to_string(),
+            if self.last_rewrite_ok.load(Ordering::Relaxed) {
+                "ok"
+            } else {
+                "err"
+            }
+            .to_string(),
+        );
+        section.insert(
+            "aof_last_write_status".to_string(),
+            if self.write_error().is_none() {
+                "ok"
+            } else {
+                "err"
+            }
+            .to_string(),
+        );
+        if self.enabled() {
+            section.insert(
+                "aof_current_size".to_string(),
+                self.current_size.load(Ordering::Relaxed).to_string(),
+            );
+            section.insert(
+                "aof_base_size".to_string(),
+                self.base_size.load(Ordering::Relaxed).to_string(),
+            );
+        }
+        section
+    }
+}
+
+// dir/appenddirname
+fn dir_path(conf: &ServerOpt) -> PathBuf {
+    let mut path = match conf.db_conf.get_dir().as_str() {
+        "" => PathBuf::from("."),
+        dir => PathBuf::from(dir),
+    };
+    path.push(&conf.appenddirname);
+    path
+}
+
+fn manifest_path(conf: &ServerOpt) -> PathBuf {
+    dir_path(conf).join(format!("{}.manifest", conf.appendfilename))
+}
+
+// a single dir/appendfilename written before the AOF was split into parts
+fn legacy_path(conf: &ServerOpt) -> PathBuf {
+    let mut path = dir_path(conf);
+    path.set_file_name(&conf.appendfilename);
+    path
+}
+
+// whether there is an AOF to load at startup
+pub fn exists(conf: &ServerOpt) -> bool {
+    manifest_path(conf).is_file() || legacy_path(conf).is_file()
+}
+
+async fn read_manifest(conf: &ServerOpt) -> Result<Option<Manifest>> {
+    let path = manifest_path(conf);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("can't read the AOF manifest {}", path.display()))?;
+    Manifest::parse(&text).map(Some)
+}
+
+// write the manifest under a temporary name and rename it over the old one
+async fn persist_manifest(conf: &ServerOpt, manifest: &Manifest) -> Result<()> {
+    let path = manifest_path(conf);
+    let tmp = dir_path(conf).join(format!("temp-{}.manifest", conf.appendfilename));
+    let mut file = File::create(&tmp)
+        .await
+        .with_context(|| format!("can't open {} for the AOF manifest", tmp.display()))?;
+    file.write_all(manifest.to_string().as_bytes()).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp, &path)
+        .await
+        .with_context(|| format!("failed renaming {} to {}", tmp.display(), path.display()))?;
+    Ok(())
+}
+
+// Move an AOF from before manifests into appenddirname and make it the base
+async fn upgrade_legacy(conf: &ServerOpt) -> Result<Manifest> {
+    let dir = dir_path(conf);
+    fs::create_dir_all(&dir).await?;
+    fs::rename(legacy_path(conf), dir.join(&conf.appendfilename))
+        .await
+        .context("can't move the old append only file into the AOF directory")?;
+    let manifest = Manifest {
+        base: Some(AofPart {
+            name: conf.appendfilename.clone(),
+            seq: 1,
+        }),
+        incrs: Vec::new(),
+        base_seq: 1,
+        incr_seq: 0,
+    };
+    persist_manifest(conf, &manifest).await?;
+    info!(
+        "Successfully migrated an old-style AOF into the AOF directory {}",
+        dir.display()
+    );
+    Ok(manifest)
+}
+
+pub fn encode_command(args: &[Bytes]) -> Vec<u8> {
+    let mut cmd = ArrayBuilder::new();
+    for a in args {
+        cmd.insert(RespType::BulkString(BulkString::new(a)));
+    }
+    cmd.build().bytes().to_vec()
+}
+
+// The commands that recreate `key`: SET, or RPUSH/SADD/ZADD/HSET with at most
+// ITEMS_PER_CMD elements each, then PEXPIREAT if it has an expire
+pub fn key_to_commands(key: &Bytes, kv: &KeyValue) -> Vec<Vec<Bytes>> {
+    let batches = |name: &'static [u8], items: Vec<Bytes>, per_item: usize| {
+        items
+            .chunks(ITEMS_PER_CMD * per_item)
+            .map(|chunk| {
+                let mut cmd = vec![Bytes::from_static(name), key.clone()];
+                cmd.extend_from_slice(chunk);
+                cmd
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut cmds = match &kv.value {
+        RedisValue::String(v) => vec![vec![Bytes::from_static(b"SET"), key.clone(), v.clone()]],
+        RedisValue::List(items) => batches(b"RPUSH", items.clone(), 1),
+        RedisValue::Set(items) => batches(b"SADD", items.clone(), 1),
+        RedisValue::SortedSet(members) => batches(
+            b"ZADD",
+            members
+                .iter()
+                .flat_map(|(m, score)| [Bytes::from(score.to_string()), m.clone()])
+                .collect(),
+            2,
+        ),
+        RedisValue::Hash(fields) => batches(
+            b"HSET",
+            fields
+                .iter()
+                .flat_map(|(f, v)| [f.clone(), v.clone()])
+                .collect(),
+            2,
+        ),
+    };
+    // an empty collection doesn't exist as a key, there is nothing to expire
+    if cmds.is_empty() {
+        return cmds;
+    }
+    if let Some(expiry) = kv.expiry {
+        cmds.push(vec![
+            Bytes::from_static(b"PEXPIREAT"),
+            key.clone(),
+            Bytes::from(expiry.as_millis().to_string()),
+        ]);
+    }
+    cmds
+}
+
+// The command to log for `args`, which ran in `db` and changed the dataset.
+// Commands with a relative or optional expiry are logged with the deadline they ended up
+// setting, read back from the keyspace.
+async fn command_to_log(server: &Server, db: u64, args: &[Bytes]) -> Vec<Bytes> {
+    let name = args[0].to_ascii_lowercase();
+    match name.as_slice() {
+        b"set" => {
+            let mut cmd = args[..3].to_vec();
+            cmd[0] = Bytes::from_static(b"SET");
+            if let Some(deadline) = server.storage.lock().await.get_expiry(db, &args[1]).await {
+                cmd.push(Bytes::from_static(b"PXAT"));
+                cmd.push(Bytes::from(deadline.to_string()));
+            }
+            cmd
+        }
+        b"expire" | b"pexpire" | b"expireat" => {
+            // no ttl left means the deadline was in the past and the key got deleted,
+            // a PEXPIREAT in the past deletes it again on replay
+            let deadline = server
+                .storage
+                .lock()
+                .await
+                .get_expiry(db, &args[1])
+                .await
+                .unwrap_or(0);
+            vec![
+                Bytes::from_static(b"PEXPIREAT"),
+                args[1].clone(),
+                Bytes::from(deadline.to_string()),
+            ]
+        }
+        _ => args.to_vec(),
+    }
+}
+
+// Append a write command executed in `db`. Does nothing while AOF is off.
+pub async fn feed(server: &Server, db: u64, args: &[Bytes]) -> Result<()> {
+    let mut guard = server.aof.file.lock().await;
+    let Some(aof) = guard.as_mut() else {
+        return Ok(());
+    };
+    let mut buf = Vec::new();
+    if aof.selected_db != Some(db) {
+        buf.extend(encode_command(&[
+            Bytes::from_static(b"SELECT"),
+            Bytes::from(db.to_string()),
+        ]));
+    }
+    buf.extend(encode_command(&command_to_log(server, db, args).await));
+    aof.pending.extend(buf);
+    aof.selected_db = Some(db);
+    let result = match aof.write_pending().await {
+        Ok(written) => {
+            server
+                .aof
+                .current_size
+                .fetch_add(written, Ordering::Relaxed);
+            if server.aof.fsync() == AppendFsync::Always {
+                let synced = aof.file.sync_data().await;
+                aof.unsynced = synced.is_err();
+                synced.context("error fsyncing the AOF")
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &result {
+        server.aof.set_write_error(Some(format!("{:#}", e)));
+    }
+    result
+}
+
+// fsync whatever was appended since the last fsync, after writing what a failed write left
+pub async fn sync(server: &Server) -> Result<()> {
+    if let Some(aof) = server.aof.file.lock().await.as_mut() {
+        let written = aof.write_pending().await?;
+        server
+            .aof
+            .current_size
+            .fetch_add(written, Ordering::Relaxed);
+        if aof.unsynced {
+            aof.file
+                .sync_data()
+                .await
+                .context("error fsyncing the AOF")?;
+            aof.unsynced = false;
+        }
+    }
+    Ok(())
+}
+
+async fn open_append(path: &Path) -> Result<AofFile> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("can't open the append only file {}", path.display()))?;
+    let len = file.metadata().await?.len();
+    Ok(AofFile {
+        file,
+        selected_db: None,
+        unsynced: false,
+        len,
+        pending: Vec::new(),
+    })
+}
+
+// Send the following writes to a new incremental file and list it in the manifest
+async fn open_new_incr(server: &Server, manifest: &mut Manifest) -> Result<()> {
+    let conf = &server.option;
+    let part = AofPart {
+        name: format!("{}.{}.incr.aof", conf.appendfilename, manifest.incr_seq + 1),
+        seq: manifest.incr_seq + 1,
+    };
+    let file = open_append(&dir_path(conf).join(&part.name)).await?;
+    let mut next = manifest.clone();
+    next.incr_seq = part.seq;
+    next.incrs.push(part);
+    persist_manifest(conf, &next).await?;
+    *manifest = next;
+
+    sync(server).await?;
+    *server.aof.file.lock().await = Some(file);
+    Ok(())
+}
+
+// Start logging after the AOF was loaded (or when there is none yet)
+pub async fn open(server: &Server) -> Result<()> {
+    fs::create_dir_all(dir_path(&server.option)).await?;
+    let mut manifest = server.aof.manifest.lock().await;
+    if manifest.base.is_none() && manifest.incrs.is_empty() {
+        // nothing on disk yet: the current dataset becomes the first base
+        drop(manifest);
+        let job = start_rewrite(server).await?;
+        return finish_rewrite(server, job).await;
+    }
+    match manifest.incrs.last() {
+        Some(last) => {
+            let path = dir_path(&server.option).join(&last.name);
+            *server.aof.file.lock().await = Some(open_append(&path).await?);
+            Ok(())
+        }
+        None => open_new_incr(server, &mut manifest).await,
+    }
+}
+
+// a rewrite that switched writes to a new incremental file and still has to write its base
+struct RewriteJob {
+    snapshot: RdbFile,
+    // the incremental files from this sequence number on come after the snapshot
+    keep_from: u64,
+}
+
+async fn start_rewrite(server: &Server) -> Result<RewriteJob> {
+    if server.aof.rewrite_in_progress.swap(true, Ordering::SeqCst) {
+        bail!(RedisError::Other(
+            "Background append only file rewriting already in progress".to_string()
+        ));
+    }
+    server
+        .aof
+        .rewrite_started
+        .store(server.clock.now_millis(), Ordering::Relaxed);
+
+    // no write can run between switching files and copying the keyspace
+    let _writes = server.aof.write_lock.lock().await;
+    let mut manifest = server.aof.manifest.lock().await;
+    let keep_from = if server.aof.enabled() {
+        if let Err(e) = open_new_incr(server, &mut manifest).await {
+            server
+                .aof
+                .rewrite_in_progress
+                .store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+        manifest.incr_seq
+    } else {
+        manifest.incr_seq + 1
+    };
+    let snapshot = server.storage.lock().await.clone();
+    Ok(RewriteJob {
+        snapshot,
+        keep_from,
+    })
+}
+
+// write the new base, then replace the old base and incremental files with it
+async fn write_base(server: &Server, job: RewriteJob) -> Result<()> {
+    let conf = &server.option;
+    let seq = {
+        let mut manifest = server.aof.manifest.lock().await;
+        manifest.base_seq += 1;
+        manifest.base_seq
+    };
+    let part = AofPart {
+        name: format!("{}.{}.base.rdb", conf.appendfilename, seq),
+        seq,
+    };
+    let dir = dir_path(conf);
+    persistence::write_rdb(
+        &job.snapshot,
+        &dir.join(&part.name),
+        server.persistence.rdb_compression(),
+    )
+    .await?;
+
+    let mut manifest = server.aof.manifest.lock().await;
+    let mut next = manifest.clone();
+    let mut replaced: Vec<AofPart> = next.base.replace(part).into_iter().collect();
+    let (kept, old) = next.incrs.into_iter().partition(|i| i.seq >= job.keep_from);
+    next.incrs = kept;
+    replaced.extend::<Vec<AofPart>>(old);
+    persist_manifest(conf, &next).await?;
+    *manifest = next;
+
+    for part in replaced {
+        if let Err(e) = fs::remove_file(dir.join(&part.name)).await {
+            error!("can't remove the old AOF file {}: {}", part.name, e);
+        }
+    }
+    let mut size = 0;
+    for part in manifest.base.iter().chain(manifest.incrs.iter()) {
+        size += fs::metadata(dir.join(&part.name)).await?.len();
+    }
+    server.aof.base_size.store(size, Ordering::Relaxed);
+    server.aof.current_size.store(size, Ordering::Relaxed);
+    Ok(())
+}
+
+async fn finish_rewrite(server: &Server, job: RewriteJob) -> Result<()> {
+    let result = write_base(server, job).await;
+    let state = &server.aof;
+    let secs = server
+        .clock
+        .now_millis()
+        .saturating_sub(state.rewrite_started.load(Ordering::Relaxed))
+        / 1000;
+    state
+        .last_rewrite_secs
+        .store(secs as i64, Ordering::Relaxed);
+    state
+        .last_rewrite_ok
+        .store(result.is_ok(), Ordering::Relaxed);
+    state.rewrite_in_progress.store(false, Ordering::SeqCst);
+    match &result {
+        Ok(()) => info!("Background AOF rewrite finished successfully"),
+        Err(e) => error!("Background AOF rewrite failed: {}", e),
+    }
+    result
+}
+
+// BGREWRITEAOF: the new base is written from a background task
+pub async fn bgrewrite(server: &Server) -> Result<()> {
+    fs::create_dir_all(dir_path(&server.option)).await?;
+    let job = start_rewrite(server).await?;
+    let server = server.clone();
+    tokio::spawn(async move {
+        let _ = finish_rewrite(&server, job).await;
+    });
+    Ok(())
+}
+
+// CONFIG SET appendonly yes|no
+pub async fn set_enabled(server: &Server, enabled: bool) -> Result<()> {
+    if enabled == server.aof.enabled() {
+        return Ok(());
+    }
+    if enabled {
+        // the rewrite opens the incremental file the following writes go to
+        server.aof.enabled.store(true, Ordering::Relaxed);
+        if let Err(e) = bgrewrite(server).await {
+            server.aof.enabled.store(false, Ordering::Relaxed);
+            return Err(e);
+        }
+        info!("Background append only file rewriting started");
+    } else {
+        sync(server).await?;
+        *server.aof.file.lock().await = None;
+        server.aof.enabled.store(false, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+// Load the base and incremental files listed in the manifest into the (empty) keyspace
+pub async fn load(server: &mut Server) -> Result<()> {
+    let manifest = match read_manifest(&server.option).await? {
+        Some(manifest) => manifest,
+        None => upgrade_legacy(&server.option).await?,
+    };
+    let dir = dir_path(&server.option);
+    let parts: Vec<&AofPart> = manifest.base.iter().chain(manifest.incrs.iter()).collect();
+    let mut size = 0;
+    for (i, part) in parts.iter().enumerate() {
+        size += load_file(server, &dir.join(&part.name), i + 1 == parts.len()).await?;
+    }
+
+    // the replayed commands are already on disk
+    {
+        let mut storage = server.storage.lock().await;
+        let dirty = storage.dirty();
+        storage.clear_dirty(dirty);
+    }
+    server.aof.base_size.store(size, Ordering::Relaxed);
+    server.aof.current_size.store(size, Ordering::Relaxed);
+    *server.aof.manifest.lock().await = manifest;
+    Ok(())
+}
+
+// What CommandReader found next
+#[derive(Debug, PartialEq)]
+pub enum AofEntry {
+    Command(Vec<Bytes>),
+    // every command was read
+    End,
+    // the file ends in the middle of a command, e.g. after a crash mid-write
+    Truncated,
+    // not a command, with the reason
+    Corrupt(String),
+}
+
+// Reads the commands of an AOF file one at a time, keeping the offset where the last
+// complete command ends: everything before it is valid.
+pub struct CommandReader {
+    decoder: RespDecoder,
+    offset: u64,
+}
+
+impl CommandReader {
+    // the commands in `data`, starting at `start` (after the RDB preamble, if any)
+    pub fn new(data: &[u8], start: usize, max_bulk_len: u64) -> Self {
+        let mut decoder = RespDecoder::new();
+        decoder.set_max_bulk_len(max_bulk_len);
+        decoder.buffer_mut().extend_from_slice(&data[start..]);
+        CommandReader {
+            decoder,
+            offset: start as u64,
+        }
+    }
+
+    // the end of the last complete command
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn next_entry(&mut self) -> AofEntry {
+        let before = self.decoder.len();
+        match self.decoder.buffer_mut().first() {
+            None => return AofEntry::End,
+            // commands are always logged as arrays, never inline
+            Some(b'*') => {}
+            Some(_) => return AofEntry::Corrupt("expected '*'".to_string()),
+        }
+        match self.decoder.decode() {
+            Ok(Some(frame)) => match frame.into_args() {
+                Ok(args) if !args.is_empty() => {
+                    self.offset += (before - self.decoder.len()) as u64;
+                    AofEntry::Command(args)
+                }
+                Ok(_) => AofEntry::Corrupt("empty command".to_string()),
+                Err(e) => AofEntry::Corrupt(e.to_string()),
+            },
+            Ok(None) => AofEntry::Truncated,
+            Err(e) => AofEntry::Corrupt(e.to_string()),
+        }
+    }
+}
+
+// The RDB preamble at the start of an AOF file, if it has one, and the offset where
+// the commands after it start
+pub async fn read_preamble(
+    data: &[u8],
+    skip_checksum: bool,
+    max_bulk_len: u64,
+) -> Result<Option<(RdbFile, usize)>> {
+    if !data.starts_with(b"REDIS") {
+        return Ok(None);
+    }
+    let mut parser = RdbParser::new(Cursor::new(data))
+        .skip_checksum(skip_checksum)
+        .max_bulk_len(max_bulk_len);
+    let rdb_file = parser.parse().await?;
+    let start = parser.into_inner().position() as usize;
+    Ok(Some((rdb_file, start)))
+}
+
+// Replay one AOF part, an RDB (or RDB preamble) followed by commands. Returns its size.
+// Only the last part may be truncated, it's cut back to its last complete command when
+// aof-load-truncated is on.
+async fn load_file(server: &mut Server, path: &Path, last: bool) -> Result<u64> {
+    let data = fs::read(path)
+        .await
+        .with_context(|| format!("can't read the append only file {}", path.display()))?;
+    let max_bulk_len = server.proto_max_bulk_len.load(Ordering::Relaxed);
+
+    let preamble = read_preamble(&data, server.option.rdb_skip_checksum, max_bulk_len)
+        .await
+        .with_context(|| {
+            format!(
+                "Bad file format reading the append only file {}",
+                path.display()
+            )
+        })?;
+    let mut start = 0;
+    if let Some((mut rdb_file, end)) = preamble {
+        start = end;
+        rdb_file.version = RDB_VERSION;
+        rdb_file.set_clock(server.clock.clone());
+        *server.storage.lock().await = rdb_file;
+    }
+
+    let mut reader = CommandReader::new(&data, start, max_bulk_len);
+    let mut client = ClientState::default();
+    let mut count = 0;
+    let mut size = data.len() as u64;
+    loop {
+        let args = match reader.next_entry() {
+            AofEntry::Command(args) => args,
+            AofEntry::End => break,
+            AofEntry::Truncated if last && server.aof.load_truncated() => {
+                size = reader.offset();
+                error!(
+                    "!!! Warning: short read while loading the AOF file {}!!!",
+                    path.display()
+                );
+                // later writes are appended to this file, they must not follow half a command
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .await?
+                    .set_len(size)
+                    .await
+                    .with_context(|| format!("can't truncate {}", path.display()))?;
+                error!(
+                    "AOF {} loaded anyway because aof-load-truncated is enabled, truncated to {} bytes",
+                    path.display(),
+                    size
+                );
+                break;
+            }
+            AofEntry::Truncated => bail!(
+                "Unexpected end of file reading the append only file {}. Exact byte offset: {}. Use redis-check-aof --fix to fix it",
+                path.display(),
+                reader.offset()
+            ),
+            AofEntry::Corrupt(reason) => bail!(
+                "Bad file format reading the append only file {} at byte offset {}: {}. Use redis-check-aof --fix to fix it",
+                path.display(),
+                reader.offset(),
+                reason
+            ),
+        };
+        if let Err(e) = commands::from_cmd_to_exec(args.clone(), None, server, &mut client).await {
+            if e.downcast_ref::<RedisError>()
+                .is_some_and(|e| matches!(e, RedisError::UnknownCommand(..)))
+            {
+                bail!(
+                    "Unknown command '{}' reading the append only file {}",
+                    String::from_utf8_lossy(&args[0]),
+                    path.display()
+                );
+            }
+            error!("error replaying the append only file: {}", e);
+        }
+        count += 1;
+    }
+    info!(
+        "DB loaded from append only file {}: {} commands",
+        path.display(),
+        count
+    );
+    Ok(size)
+}
+
+// fsyncs once per second with appendfsync everysec and starts automatic rewrites
+pub async fn run(server: Server) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        // after a failed write or fsync it is retried every second until it works
+        let failed = server.aof.write_error().is_some();
+        if failed || server.aof.fsync() == AppendFsync::EverySec {
+            match sync(&server).await {
+                Ok(()) if failed => {
+                    server.aof.set_write_error(None);
+                    info!("AOF write error looks solved, Redis can write again.");
+                }
+                Ok(()) => {}
+                Err(e) => {
+                    error!("error writing the append only file: {:#}", e);
+                    server.aof.set_write_error(Some(format!("{:#}", e)));
+                }
+            }
+        }
+        if server.is_shutting_down() {
+            continue;
+        }
+        if let Some(growth) = server.aof.due_rewrite() {
+            info!("Starting automatic rewriting of AOF on {}% growth", growth);
+            if let Err(e) = bgrewrite(&server).await {
+                error!("automatic AOF rewrite failed to start: {}", e);
+            }
+        }
+    }
+}
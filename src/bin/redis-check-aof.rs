@@ -0,0 +1,176 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use codecrafters_redis::{
+    aof::{self, AofEntry, CommandReader},
+    server,
+};
+
+// Offline check of an append-only file, like redis-check-aof: reports where the last
+// valid command ends and, with --fix, truncates the file there once confirmed.
+// Given a manifest it checks every file it lists; only the last one may be fixed, since
+// cutting an earlier one would lose the commands that follow it.
+
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Args {
+    // truncate the file after its last valid command
+    #[arg(long)]
+    fix: bool,
+
+    // don't verify the checksum of an RDB preamble
+    #[arg(long)]
+    rdb_skip_checksum: bool,
+
+    // largest string accepted, the proto-max-bulk-len of the server, e.g. "512mb"
+    #[arg(long, default_value = "512mb", value_parser = server::parse_memory)]
+    max_bulk_len: u64,
+
+    // an AOF file, or the .manifest of a multi-part AOF
+    file: PathBuf,
+}
+
+// how checking one file went
+enum Outcome {
+    Valid,
+    // not valid from this offset on
+    Invalid(u64),
+}
+
+async fn check_file(path: &Path, args: &Args) -> Result<Outcome> {
+    let data = std::fs::read(path).with_context(|| format!("can't read {}", path.display()))?;
+    println!("Checking {}", path.display());
+
+    let mut start = 0;
+    match aof::read_preamble(&data, args.rdb_skip_checksum, args.max_bulk_len).await {
+        Ok(Some((rdb_file, end))) => {
+            println!(
+                "RDB preamble is OK ({} keys), proceeding with the AOF tail at offset {}",
+                rdb_file
+                    .databases
+                    .iter()
+                    .map(|e| e.value().len())
+                    .sum::<usize>(),
+                end
+            );
+            start = end;
+        }
+        Ok(None) => {}
+        Err(e) => {
+            println!("RDB preamble of the AOF file is not sane: {:#}", e);
+            return Ok(Outcome::Invalid(0));
+        }
+    }
+
+    let mut reader = CommandReader::new(&data, start, args.max_bulk_len);
+    let mut count = 0;
+    let error = loop {
+        match reader.next_entry() {
+            AofEntry::Command(_) => count += 1,
+            AofEntry::End => break None,
+            AofEntry::Truncated => break Some("unexpected end of file".to_string()),
+            AofEntry::Corrupt(reason) => break Some(reason),
+        }
+    };
+    let ok_up_to = reader.offset();
+    let line = data[..ok_up_to as usize]
+        .iter()
+        .filter(|b| **b == b'\n')
+        .count()
+        + 1;
+    println!(
+        "AOF analyzed: filename={}, size={}, ok_up_to={}, ok_up_to_line={}, diff={}, commands={}",
+        path.display(),
+        data.len(),
+        ok_up_to,
+        line,
+        data.len() as u64 - ok_up_to,
+        count
+    );
+    match error {
+        None => {
+            println!("AOF {} is valid", path.display());
+            Ok(Outcome::Valid)
+        }
+        Some(reason) => {
+            println!(
+                "AOF {} format error at offset {}: {}",
+                path.display(),
+                ok_up_to,
+                reason
+            );
+            Ok(Outcome::Invalid(ok_up_to))
+        }
+    }
+}
+
+fn fix_file(path: &Path, ok_up_to: u64) -> Result<()> {
+    let len = std::fs::metadata(path)?.len();
+    println!(
+        "This will shrink the AOF {} from {} bytes, with {} bytes, to {} bytes",
+        path.display(),
+        len,
+        len - ok_up_to,
+        ok_up_to
+    );
+    print!("Continue? [y/N]: ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        println!("Aborting...");
+        process::exit(1);
+    }
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(ok_up_to)
+        .with_context(|| format!("failed to truncate {}", path.display()))?;
+    println!("Successfully truncated AOF {}", path.display());
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let files = if args.file.extension().is_some_and(|e| e == "manifest") {
+        let text = std::fs::read_to_string(&args.file)
+            .with_context(|| format!("can't read {}", args.file.display()))?;
+        let dir = args.file.parent().unwrap_or(Path::new("."));
+        aof::manifest_files(&text)?
+            .into_iter()
+            .map(|name| dir.join(name))
+            .collect()
+    } else {
+        vec![args.file.clone()]
+    };
+
+    for (i, path) in files.iter().enumerate() {
+        let Outcome::Invalid(ok_up_to) = check_file(path, &args).await? else {
+            continue;
+        };
+        let last = i + 1 == files.len();
+        if !args.fix {
+            println!("AOF is not valid. Use the --fix option to try fixing it.");
+        } else if !last {
+            println!(
+                "Only the last AOF file of a manifest can be fixed, {} is not",
+                path.display()
+            );
+        } else if ok_up_to == 0 {
+            println!("Nothing valid in {}, not fixing it", path.display());
+        } else {
+            fix_file(path, ok_up_to)?;
+            continue;
+        }
+        process::exit(1);
+    }
+    Ok(())
+}
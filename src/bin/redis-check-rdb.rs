@@ -0,0 +1,180 @@
+use std::{
+    collections::BTreeMap,
+    io::Cursor,
+    path::PathBuf,
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde_json::{json, Map, Value};
+
+use codecrafters_redis::{
+    db::{ChecksumStatus, RdbFile, RdbParser},
+    json::{bytes_to_json, value_to_json},
+    server,
+};
+
+// Offline check of an RDB dump, like redis-check-rdb: parses the whole file and reports
+// its version, aux fields, per-database key and expiry counts, value types and checksum,
+// or the offset where parsing failed. --json prints the contents instead, with the keys
+// of every database sorted, so two dumps can be diffed.
+
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Args {
+    // print the contents as JSON instead of the report
+    #[arg(long)]
+    json: bool,
+
+    // largest string accepted, e.g. "512mb"
+    #[arg(long, default_value = "512mb", value_parser = server::parse_memory)]
+    max_bulk_len: u64,
+
+    file: PathBuf,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn sorted_aux(rdb_file: &RdbFile) -> BTreeMap<String, String> {
+    rdb_file
+        .aux_fields
+        .iter()
+        .map(|e| (e.key().clone(), e.value().clone()))
+        .collect()
+}
+
+fn report(rdb_file: &RdbFile, checksum: ChecksumStatus) {
+    println!("RDB version {}", rdb_file.version);
+    for (k, v) in sorted_aux(rdb_file) {
+        println!("AUX FIELD {} = '{}'", k, v);
+    }
+
+    let now = now_millis();
+    let mut dbs: Vec<u64> = rdb_file.databases.iter().map(|e| *e.key()).collect();
+    dbs.sort();
+    let (mut total_keys, mut total_expires) = (0, 0);
+    for db in dbs {
+        let Some(keys) = rdb_file.databases.get(&db) else {
+            continue;
+        };
+        let mut types: BTreeMap<&str, usize> = BTreeMap::new();
+        let (mut expires, mut expired) = (0, 0);
+        for e in keys.iter() {
+            *types.entry(e.value().value.type_name()).or_default() += 1;
+            if let Some(expiry) = e.value().expiry {
+                expires += 1;
+                if expiry.is_expired(now) {
+                    expired += 1;
+                }
+            }
+        }
+        println!(
+            "DB {}: {} keys, {} with an expire ({} already expired)",
+            db,
+            keys.len(),
+            expires,
+            expired
+        );
+        for (name, count) in types {
+            println!("    {}: {}", name, count);
+        }
+        total_keys += keys.len();
+        total_expires += expires;
+    }
+    println!("Keys: {}, expires: {}", total_keys, total_expires);
+
+    match checksum {
+        ChecksumStatus::Missing => println!("No checksum (RDB version < 5)"),
+        ChecksumStatus::Disabled => println!("Checksum disabled when the file was saved"),
+        ChecksumStatus::Ok(sum) => println!("Checksum OK ({:016x})", sum),
+        ChecksumStatus::Mismatch { stored, computed } => println!(
+            "Wrong checksum: stored {:016x}, computed {:016x}",
+            stored, computed
+        ),
+    }
+}
+
+fn to_json(rdb_file: &RdbFile) -> Value {
+    let mut databases = Map::new();
+    for e in rdb_file.databases.iter() {
+        let mut keys: Vec<_> = e
+            .value()
+            .iter()
+            .map(|kv| (kv.key().clone(), kv.value().clone()))
+            .collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        let keys = keys
+            .iter()
+            .map(|(key, kv)| {
+                json!({
+                    "key": bytes_to_json(key),
+                    "type": kv.value.type_name(),
+                    "expire_at": kv.expiry.map(|e| e.as_millis()),
+                    "value": value_to_json(&kv.value),
+                })
+            })
+            .collect();
+        databases.insert(e.key().to_string(), Value::Array(keys));
+    }
+    json!({
+        "version": rdb_file.version,
+        "aux": sorted_aux(rdb_file),
+        "databases": databases,
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let data =
+        std::fs::read(&args.file).with_context(|| format!("can't read {}", args.file.display()))?;
+
+    // a wrong checksum is reported rather than stopping the parse
+    let mut parser = RdbParser::new(Cursor::new(data.as_slice()))
+        .skip_checksum(true)
+        .max_bulk_len(args.max_bulk_len);
+    let rdb_file = match parser.parse().await {
+        Ok(rdb_file) => rdb_file,
+        Err(e) => {
+            let entry = parser.entry_offset();
+            let offset = parser.into_inner().position();
+            println!("--- RDB ERROR DETECTED ---");
+            println!(
+                "[offset {}] {:#} (in the entry starting at offset {})",
+                offset, e, entry
+            );
+            println!(
+                "[additional info] file {}, size {}",
+                args.file.display(),
+                data.len()
+            );
+            process::exit(1);
+        }
+    };
+    let checksum = parser.checksum();
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&to_json(&rdb_file))?);
+    } else {
+        println!(
+            "Checking RDB file {} ({} bytes)",
+            args.file.display(),
+            data.len()
+        );
+        report(&rdb_file, checksum);
+    }
+    if let ChecksumStatus::Mismatch { .. } = checksum {
+        process::exit(1);
+    }
+    if !args.json {
+        println!("RDB looks OK!");
+    }
+    Ok(())
+}
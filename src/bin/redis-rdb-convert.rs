@@ -0,0 +1,179 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Cursor, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use clap::{Parser, Subcommand};
+
+use codecrafters_redis::{
+    aof,
+    db::{KeyValue, RdbFile, RdbParser, RDB_VERSION},
+    json, persistence,
+};
+
+// Moves data in and out of RDB files:
+//   to-json   one JSON record per key, see json::key_to_json
+//   from-json the same records back into an RDB file
+//   to-resp   the commands that recreate every key, for `redis-cli --pipe`
+// Keys that already expired are left out of to-json and to-resp.
+
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Args {
+    #[command(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    // RDB to newline-delimited JSON
+    ToJson {
+        rdb: PathBuf,
+        // write here instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    // newline-delimited JSON to RDB
+    FromJson {
+        json: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        // LZF-compress long strings
+        #[arg(long, default_value = "yes", value_parser = ["yes", "no"])]
+        rdbcompression: String,
+    },
+    // RDB to a RESP command stream
+    ToResp {
+        rdb: PathBuf,
+        // write here instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+async fn read_rdb(path: &Path) -> Result<RdbFile> {
+    let data = std::fs::read(path).with_context(|| format!("can't read {}", path.display()))?;
+    RdbParser::new(Cursor::new(data.as_slice()))
+        .parse()
+        .await
+        .with_context(|| format!("can't parse {}", path.display()))
+}
+
+fn open_output(path: &Option<PathBuf>) -> Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("can't create {}", path.display()))?,
+        )),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    })
+}
+
+// the keys that haven't expired by `now`, by database and then key
+fn live_keys(rdb_file: &RdbFile, now: u64) -> (Vec<(u64, Bytes, KeyValue)>, usize) {
+    let mut keys = Vec::new();
+    let mut expired = 0;
+    for db in rdb_file.databases.iter() {
+        for e in db.value().iter() {
+            if e.value().is_expired(now) {
+                expired += 1;
+            } else {
+                keys.push((*db.key(), e.key().clone(), e.value().clone()));
+            }
+        }
+    }
+    keys.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+    (keys, expired)
+}
+
+async fn to_json(rdb: &Path, output: &Option<PathBuf>) -> Result<()> {
+    let now = now_millis();
+    let (keys, expired) = live_keys(&read_rdb(rdb).await?, now);
+    let mut out = open_output(output)?;
+    for (db, key, kv) in &keys {
+        serde_json::to_writer(&mut out, &json::key_to_json(*db, key, kv, now))?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    eprintln!(
+        "{} keys exported, {} expired keys skipped",
+        keys.len(),
+        expired
+    );
+    Ok(())
+}
+
+async fn from_json(input: &Path, output: &Path, compression: bool) -> Result<()> {
+    let now = now_millis();
+    let file = File::open(input).with_context(|| format!("can't read {}", input.display()))?;
+    let mut rdb_file = RdbFile::new(RDB_VERSION);
+    let mut count = 0;
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (db, key, kv) = serde_json::from_str(&line)
+            .map_err(anyhow::Error::from)
+            .and_then(|record| json::key_from_json(&record, now))
+            .with_context(|| format!("{} line {}", input.display(), n + 1))?;
+        rdb_file.insert_or_replace(db, key, kv).await;
+        count += 1;
+    }
+    persistence::write_rdb(&rdb_file, output, compression).await?;
+    eprintln!("{} keys written to {}", count, output.display());
+    Ok(())
+}
+
+async fn to_resp(rdb: &Path, output: &Option<PathBuf>) -> Result<()> {
+    let (keys, expired) = live_keys(&read_rdb(rdb).await?, now_millis());
+    let mut out = open_output(output)?;
+    let mut selected = None;
+    let mut exported = 0;
+    for (db, key, kv) in &keys {
+        let cmds = aof::key_to_commands(key, kv);
+        if cmds.is_empty() {
+            continue;
+        }
+        if selected != Some(*db) {
+            out.write_all(&aof::encode_command(&[
+                Bytes::from_static(b"SELECT"),
+                Bytes::from(db.to_string()),
+            ]))?;
+            selected = Some(*db);
+        }
+        for cmd in cmds {
+            out.write_all(&aof::encode_command(&cmd))?;
+        }
+        exported += 1;
+    }
+    out.flush()?;
+    eprintln!(
+        "{} keys exported, {} expired keys skipped",
+        exported, expired
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    match Args::parse().cmd {
+        Cmd::ToJson { rdb, output } => to_json(&rdb, &output).await,
+        Cmd::FromJson {
+            json,
+            output,
+            rdbcompression,
+        } => from_json(&json, &output, rdbcompression == "yes").await,
+        Cmd::ToResp { rdb, output } => to_resp(&rdb, &output).await,
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    fmt::Debug,
+    future::{self, Future},
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// Source of "now" for everything expiry related (lazy/active expire, TTL, SET EX ...).
+// The server owns one clock and shares it with its storage, so a test can swap in a
+// MockClock and move time forward without sleeping.
+pub trait Clock: Send + Sync + Debug {
+    // unix time in milliseconds
+    fn now_millis(&self) -> u64;
+
+    // wait until `d` has passed on this clock (DEBUG SLEEP)
+    fn sleep(&self, d: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+// Wall clock read once at startup and advanced with a monotonic timer afterwards,
+// so a jump of the system clock doesn't expire keys early or keep them alive.
+#[derive(Debug)]
+pub struct SystemClock {
+    start_millis: u64,
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start_millis: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("now time get error")
+                .as_millis() as u64,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        self.start_millis + self.start.elapsed().as_millis() as u64
+    }
+
+    fn sleep(&self, d: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(d))
+    }
+}
+
+// A clock that only moves when told to
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: AtomicU64,
+}
+
+impl MockClock {
+    pub fn new(now_millis: u64) -> Self {
+        MockClock {
+            now: AtomicU64::new(now_millis),
+        }
+    }
+
+    pub fn advance(&self, d: Duration) {
+        self.now.fetch_add(d.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, now_millis: u64) {
+        self.now.store(now_millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_millis(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+
+    // time passes at once
+    fn sleep(&self, d: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        self.advance(d);
+        Box::pin(future::ready(()))
+    }
+}
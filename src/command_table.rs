@@ -0,0 +1,455 @@
+use bytes::Bytes;
+use resp_protocol::{Array, ArrayBuilder, BulkString, Integer, RespType, SimpleString};
+
+// command flags, reported by COMMAND INFO under the names Redis uses
+pub const WRITE: &str = "write";
+pub const READONLY: &str = "readonly";
+pub const ADMIN: &str = "admin";
+pub const PUBSUB: &str = "pubsub";
+pub const NOSCRIPT: &str = "noscript";
+pub const FAST: &str = "fast";
+pub const LOADING: &str = "loading";
+pub const STALE: &str = "stale";
+
+// A registered command.
+// `arity` counts the command name: a positive value is exact, a negative one is a minimum.
+// `first_key`/`last_key`/`step` locate the keys in the argument list (last_key -1 = last arg).
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    pub first_key: i64,
+    pub last_key: i64,
+    pub step: i64,
+    pub group: &'static str,
+    pub since: &'static str,
+    pub summary: &'static str,
+}
+
+pub static COMMAND_TABLE: &[CommandSpec] = &[
+    CommandSpec {
+        name: "ping",
+        arity: -1,
+        flags: &[FAST, STALE],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "connection",
+        since: "1.0.0",
+        summary: "Returns the server's liveliness response.",
+    },
+    CommandSpec {
+        name: "echo",
+        arity: 2,
+        flags: &[FAST],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "connection",
+        since: "1.0.0",
+        summary: "Returns the given string.",
+    },
+    CommandSpec {
+        name: "get",
+        arity: 2,
+        flags: &[READONLY, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "1.0.0",
+        summary: "Returns the string value of a key.",
+    },
+    CommandSpec {
+        name: "set",
+        arity: -3,
+        flags: &[WRITE],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "1.0.0",
+        summary: "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "getset",
+        arity: 3,
+        flags: &[WRITE],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "1.0.0",
+        summary: "Returns the previous string value of a key after setting it to a new value.",
+    },
+    CommandSpec {
+        name: "expire",
+        arity: -3,
+        flags: &[WRITE, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Sets the expiration time of a key in seconds.",
+    },
+    CommandSpec {
+        name: "pexpire",
+        arity: -3,
+        flags: &[WRITE, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "2.6.0",
+        summary: "Sets the expiration time of a key in milliseconds.",
+    },
+    CommandSpec {
+        name: "expireat",
+        arity: -3,
+        flags: &[WRITE, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "1.2.0",
+        summary: "Sets the expiration time of a key to a Unix timestamp.",
+    },
+    CommandSpec {
+        name: "pexpireat",
+        arity: -3,
+        flags: &[WRITE, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "2.6.0",
+        summary: "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+    },
+    CommandSpec {
+        name: "ttl",
+        arity: 2,
+        flags: &[READONLY, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Returns the expiration time in seconds of a key.",
+    },
+    CommandSpec {
+        name: "pttl",
+        arity: 2,
+        flags: &[READONLY, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "2.6.0",
+        summary: "Returns the expiration time in milliseconds of a key.",
+    },
+    CommandSpec {
+        name: "expiretime",
+        arity: 2,
+        flags: &[READONLY, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "7.0.0",
+        summary: "Returns the expiration time of a key as a Unix timestamp.",
+    },
+    CommandSpec {
+        name: "pexpiretime",
+        arity: 2,
+        flags: &[READONLY, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "7.0.0",
+        summary: "Returns the expiration time of a key as a Unix milliseconds timestamp.",
+    },
+    CommandSpec {
+        name: "persist",
+        arity: 2,
+        flags: &[WRITE, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "2.2.0",
+        summary: "Removes the expiration time of a key.",
+    },
+    CommandSpec {
+        name: "move",
+        arity: 3,
+        flags: &[WRITE, FAST],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Moves a key to another database.",
+    },
+    CommandSpec {
+        name: "copy",
+        arity: -3,
+        flags: &[WRITE],
+        first_key: 1,
+        last_key: 2,
+        step: 1,
+        group: "generic",
+        since: "6.2.0",
+        summary: "Copies the value of a key to a new key.",
+    },
+    CommandSpec {
+        name: "keys",
+        arity: 2,
+        flags: &[READONLY],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Returns all key names that match a pattern.",
+    },
+    CommandSpec {
+        name: "select",
+        arity: 2,
+        flags: &[LOADING, STALE, FAST],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "connection",
+        since: "1.0.0",
+        summary: "Changes the selected database.",
+    },
+    CommandSpec {
+        name: "swapdb",
+        arity: 3,
+        flags: &[WRITE, FAST],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "4.0.0",
+        summary: "Swaps two Redis databases.",
+    },
+    CommandSpec {
+        name: "dbsize",
+        arity: 1,
+        flags: &[READONLY, FAST],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Returns the number of keys in the database.",
+    },
+    CommandSpec {
+        name: "config",
+        arity: -2,
+        flags: &[ADMIN, NOSCRIPT, LOADING, STALE],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "2.0.0",
+        summary: "A container for server configuration commands.",
+    },
+    CommandSpec {
+        name: "save",
+        arity: 1,
+        flags: &[ADMIN, NOSCRIPT],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Synchronously saves the database(s) to disk.",
+    },
+    CommandSpec {
+        name: "bgsave",
+        arity: -1,
+        flags: &[ADMIN, NOSCRIPT],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Asynchronously saves the database(s) to disk.",
+    },
+    CommandSpec {
+        name: "bgrewriteaof",
+        arity: 1,
+        flags: &[ADMIN, NOSCRIPT],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Asynchronously rewrites the append-only file to disk.",
+    },
+    CommandSpec {
+        name: "lastsave",
+        arity: 1,
+        flags: &[FAST, LOADING, STALE],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Returns the Unix timestamp of the last successful save to disk.",
+    },
+    CommandSpec {
+        name: "shutdown",
+        arity: -1,
+        flags: &[ADMIN, NOSCRIPT, LOADING, STALE],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Synchronously saves the database(s) to disk and shuts down the Redis server.",
+    },
+    CommandSpec {
+        name: "debug",
+        arity: -2,
+        flags: &[ADMIN, NOSCRIPT, LOADING, STALE],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "A container for debugging commands.",
+    },
+    CommandSpec {
+        name: "info",
+        arity: -1,
+        flags: &[LOADING, STALE],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Returns information and statistics about the server.",
+    },
+    CommandSpec {
+        name: "replconf",
+        arity: -1,
+        flags: &[ADMIN, NOSCRIPT, LOADING, STALE],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "3.0.0",
+        summary: "An internal command for configuring the replication stream.",
+    },
+    CommandSpec {
+        name: "psync",
+        arity: -3,
+        flags: &[ADMIN, NOSCRIPT],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "2.8.0",
+        summary: "An internal command used in replication.",
+    },
+    CommandSpec {
+        name: "command",
+        arity: -1,
+        flags: &[LOADING, STALE],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns detailed information about all commands.",
+    },
+];
+
+// find a command by name, case-insensitively
+pub fn lookup(name: &[u8]) -> Option<&'static CommandSpec> {
+    COMMAND_TABLE
+        .iter()
+        .find(|c| c.name.as_bytes().eq_ignore_ascii_case(name))
+}
+
+impl CommandSpec {
+    // `argc` includes the command name
+    pub fn check_arity(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity >= 0 {
+            argc == self.arity
+        } else {
+            argc >= -self.arity
+        }
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.has_flag(WRITE)
+    }
+
+    // the key arguments of a full command line (args[0] is the command name)
+    pub fn get_keys<'a>(&self, args: &'a [Bytes]) -> Vec<&'a Bytes> {
+        if self.first_key <= 0 || self.step <= 0 {
+            return Vec::new();
+        }
+        let last = if self.last_key < 0 {
+            args.len() as i64 + self.last_key
+        } else {
+            self.last_key.min(args.len() as i64 - 1)
+        };
+        (self.first_key..=last)
+            .step_by(self.step as usize)
+            .map(|i| &args[i as usize])
+            .collect()
+    }
+
+    // the COMMAND INFO entry of this command
+    pub fn info(&self) -> Array {
+        let mut flags = ArrayBuilder::new();
+        for f in self.flags {
+            flags.insert(RespType::SimpleString(SimpleString::new(f.as_bytes())));
+        }
+        let mut categories = ArrayBuilder::new();
+        categories.insert(RespType::SimpleString(SimpleString::new(
+            format!("@{}", self.group).as_bytes(),
+        )));
+
+        let mut info = ArrayBuilder::new();
+        info.insert(RespType::BulkString(BulkString::new(self.name.as_bytes())))
+            .insert(RespType::Integer(Integer::new(self.arity)))
+            .insert(RespType::Array(flags.build()))
+            .insert(RespType::Integer(Integer::new(self.first_key)))
+            .insert(RespType::Integer(Integer::new(self.last_key)))
+            .insert(RespType::Integer(Integer::new(self.step)))
+            .insert(RespType::Array(categories.build()))
+            // tips, key specs and subcommands are not tracked
+            .insert(RespType::Array(ArrayBuilder::new().build()))
+            .insert(RespType::Array(ArrayBuilder::new().build()))
+            .insert(RespType::Array(ArrayBuilder::new().build()));
+        info.build()
+    }
+
+    // the COMMAND DOCS entry of this command
+    pub fn docs(&self) -> Array {
+        let mut docs = ArrayBuilder::new();
+        docs.insert(RespType::BulkString(BulkString::new(b"summary")))
+            .insert(RespType::BulkString(BulkString::new(
+                self.summary.as_bytes(),
+            )))
+            .insert(RespType::BulkString(BulkString::new(b"since")))
+            .insert(RespType::BulkString(BulkString::new(self.since.as_bytes())))
+            .insert(RespType::BulkString(BulkString::new(b"group")))
+            .insert(RespType::BulkString(BulkString::new(self.group.as_bytes())));
+        docs.build()
+    }
+}
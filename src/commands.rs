@@ -1,16 +1,24 @@
 use std::{
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
 };
 
 use crate::{
-    db::{Dbconf, Expiry, KeyValue, RdbFile, RedisValue, DB_NUM},
+    aof::{self, AppendFsync},
+    command_table::{self, CommandSpec, COMMAND_TABLE},
+    db::{Dbconf, Expiry, KeyValue, RdbFile, RedisValue},
+    error::RedisError,
+    persistence,
     replication::Replication,
-    server::Server,
+    resp::MIN_PROTO_MAX_BULK_LEN,
+    server::{self, Server, ShutdownMode},
 };
 use anyhow::{bail, Result};
+use bytes::Bytes;
 use log::error;
-use resp_protocol::{ArrayBuilder, BulkString, Error, RespType, SimpleString, NULL_BULK_STRING};
+use resp_protocol::{
+    ArrayBuilder, BulkString, Error, Integer, RespType, SimpleString, NULL_BULK_STRING,
+};
 use tokio::{
     io::{AsyncWriteExt, Interest},
     net::TcpStream,
@@ -23,13 +31,13 @@ pub struct Ping;
 #[derive(Clone, Debug)]
 pub struct Echo(BulkString);
 
-pub struct Get<'a>(String, &'a Server);
+pub struct Get<'a>(Bytes, u64, &'a Server);
 
-pub struct Set<'a>(String, KeyValue, &'a Server);
+pub struct Set<'a>(Bytes, RedisValue, SetOptions, u64, &'a Server);
 
-pub struct Keys<'a>(&'a [&'a [u8]], Arc<Mutex<RdbFile>>);
+pub struct Keys<'a>(&'a [Bytes], u64, Arc<Mutex<RdbFile>>);
 
-pub struct Repl<'a>(&'a [&'a [u8]], &'a mut Server, Arc<Mutex<TcpStream>>);
+pub struct Repl<'a>(&'a [Bytes], &'a mut Server, Arc<Mutex<TcpStream>>);
 
 impl Ping {
     fn exec(&self) -> Result<Vec<u8>> {
@@ -44,94 +52,405 @@ impl Echo {
 }
 impl Get<'_> {
     async fn exec<'a>(&'a mut self) -> Result<Vec<u8>> {
-        let mut db = self.1.storage.lock().await;
-
-        if let Some(value) = db.get(DB_NUM, &self.0).await {
-            match value.expiry {
-                Some(exp_t) => match exp_t {
-                    Expiry::Milliseconds(t) => {
-                        let now_millis = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .expect("now time get error")
-                            .as_millis();
-                        log::debug!("timestamp:{:?} ms vs time now :{:?} ms", t, now_millis);
-                        if (t as u128) < now_millis {
-                            log::debug!("delete a key {}", db.delete(0, &self.0).await);
-                            Ok(NULL_BULK_STRING.bytes().to_vec())
-                        } else {
-                            get_value_from_redis_type(&value.value)
-                        }
-                    }
-                    Expiry::Seconds(t) => {
-                        let now_secs = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .expect("now time get error")
-                            .as_secs();
-                        log::debug!("timestamp:{:?} ms vs time now :{:?} ms", t, now_secs);
-                        if (t as u64) < now_secs {
-                            log::debug!("delete a key {}", db.delete(0, &self.0).await);
-                            Ok(NULL_BULK_STRING.bytes().to_vec())
-                        } else {
-                            get_value_from_redis_type(&value.value)
-                        }
-                    }
-                },
-                None => get_value_from_redis_type(&value.value),
-            }
-        } else {
-            Ok(NULL_BULK_STRING.bytes().to_vec())
+        let mut db = self.2.storage.lock().await;
+
+        match lookup_key(&mut db, self.2, self.1, &self.0).await {
+            Some(value) => get_value_from_redis_type(&value.value),
+            None => Ok(NULL_BULK_STRING.bytes().to_vec()),
         }
     }
 }
+
+// Read a key, deleting it first if its ttl has passed (lazy expiration)
+async fn lookup_key(
+    db: &mut RdbFile,
+    server: &Server,
+    db_num: u64,
+    key: &[u8],
+) -> Option<KeyValue> {
+    let kv = db.get(db_num, key).await?;
+    if kv.is_expired(server.clock.now_millis()) {
+        log::debug!("delete a key {}", db.delete_expired(db_num, key).await);
+        server.stats.incr_expired_keys(1);
+        return None;
+    }
+    Some(kv)
+}
+
 //TODO:other redis type map
 fn get_value_from_redis_type(v: &RedisValue) -> Result<Vec<u8>> {
     match v {
         RedisValue::String(s) => {
-            log::debug!("get v is {s}");
-            Ok(BulkString::new(s.as_bytes()).bytes().to_vec())
+            log::debug!("get v is {:?}", s);
+            Ok(BulkString::new(s).bytes().to_vec())
         }
-        _ => Ok(NULL_BULK_STRING.bytes().to_vec()),
+        _ => bail!(RedisError::WrongType),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SetCondition {
+    Always,
+    // NX: only set a key that doesn't exist
+    IfAbsent,
+    // XX: only set a key that already exists
+    IfPresent,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SetTtl {
+    // no expiry option: the new value has no ttl
+    Clear,
+    // KEEPTTL
+    Keep,
+    // EX / PX / EXAT / PXAT
+    At(Expiry),
+}
+
+// SET key value [NX | XX] [GET] [EX seconds | PX milliseconds |
+//     EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]
+#[derive(Debug, Clone)]
+pub struct SetOptions {
+    condition: SetCondition,
+    get: bool,
+    ttl: SetTtl,
+}
+
+impl SetOptions {
+    // GETSET key value: SET key value GET
+    fn getset() -> Self {
+        SetOptions {
+            condition: SetCondition::Always,
+            get: true,
+            ttl: SetTtl::Clear,
+        }
+    }
+
+    // options may come in any order and any case, `now_millis` is the base of EX/PX
+    fn parse(opts: &[Bytes], now_millis: u64) -> Result<Self> {
+        let mut ret = SetOptions {
+            condition: SetCondition::Always,
+            get: false,
+            ttl: SetTtl::Clear,
+        };
+        let mut i = 0;
+        while i < opts.len() {
+            let opt = opts[i].to_ascii_uppercase();
+            match opt.as_slice() {
+                b"NX" if ret.condition == SetCondition::Always => {
+                    ret.condition = SetCondition::IfAbsent
+                }
+                b"XX" if ret.condition == SetCondition::Always => {
+                    ret.condition = SetCondition::IfPresent
+                }
+                b"GET" => ret.get = true,
+                b"KEEPTTL" if ret.ttl == SetTtl::Clear => ret.ttl = SetTtl::Keep,
+                b"EX" | b"PX" | b"EXAT" | b"PXAT" if ret.ttl == SetTtl::Clear => {
+                    i += 1;
+                    let Some(arg) = opts.get(i) else {
+                        bail!(RedisError::Syntax);
+                    };
+                    let Some(n) = arg_to_i64(arg) else {
+                        bail!(RedisError::NotInteger);
+                    };
+                    if n <= 0 {
+                        bail!(RedisError::Other(
+                            "invalid expire time in 'set' command".to_string()
+                        ));
+                    }
+                    let n = n as u64;
+                    // like Redis the deadline in ms has to fit an i64, TTL and PTTL report it as one
+                    let deadline = match opt.as_slice() {
+                        b"EX" => n
+                            .checked_mul(1000)
+                            .and_then(|ms| now_millis.checked_add(ms)),
+                        b"PX" => now_millis.checked_add(n),
+                        b"EXAT" => n.checked_mul(1000),
+                        _ => Some(n),
+                    }
+                    .filter(|deadline| *deadline <= i64::MAX as u64);
+                    let Some(deadline) = deadline else {
+                        bail!(RedisError::Other(
+                            "invalid expire time in 'set' command".to_string()
+                        ));
+                    };
+                    ret.ttl = SetTtl::At(Expiry::from_millis(deadline));
+                }
+                _ => bail!(RedisError::Syntax),
+            }
+            i += 1;
+        }
+        Ok(ret)
     }
 }
 
 impl Set<'_> {
     async fn exec(&mut self) -> Result<Vec<u8>> {
-        self.2
-            .storage
-            .lock()
-            .await
-            .insert(
-                DB_NUM,
-                self.0.clone(),
-                self.1.value.clone(),
-                self.1.expiry.clone(),
-            )
-            .await;
-        Ok(SimpleString::new(b"OK").bytes().to_vec())
+        let mut db = self.4.storage.lock().await;
+
+        // GET fails on a non-string value before anything is written
+        let live = db.get_live(self.3, &self.0).await;
+        if self.2.get {
+            if let Some(kv) = &live {
+                if !matches!(kv.value, RedisValue::String(_)) {
+                    bail!(RedisError::WrongType);
+                }
+            }
+        }
+
+        let expiry = match &self.2.ttl {
+            SetTtl::Clear => None,
+            SetTtl::Keep => live.and_then(|kv| kv.expiry),
+            SetTtl::At(e) => Some(*e),
+        };
+        let kv = KeyValue {
+            value: self.1.clone(),
+            expiry,
+        };
+        let key = self.0.clone();
+        let (old, written) = match self.2.condition {
+            SetCondition::Always => (db.insert_or_replace(self.3, key, kv).await, true),
+            SetCondition::IfAbsent => match db.insert_if_absent(self.3, key, kv).await {
+                Some(old) => (Some(old), false),
+                None => (None, true),
+            },
+            SetCondition::IfPresent => match db.update_if_present(self.3, key, kv).await {
+                Some(old) => (Some(old), true),
+                None => (None, false),
+            },
+        };
+
+        if self.2.get {
+            match old {
+                Some(kv) => get_value_from_redis_type(&kv.value),
+                None => Ok(NULL_BULK_STRING.bytes().to_vec()),
+            }
+        } else if written {
+            Ok(SimpleString::new(b"OK").bytes().to_vec())
+        } else {
+            Ok(NULL_BULK_STRING.bytes().to_vec())
+        }
     }
 }
+// how EXPIRE-like commands interpret their time argument
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpireUnit {
+    // EXPIRE: seconds from now
+    Seconds,
+    // PEXPIRE: milliseconds from now
+    Milliseconds,
+    // EXPIREAT: unix time in seconds
+    UnixSeconds,
+    // PEXPIREAT: unix time in milliseconds
+    UnixMilliseconds,
+}
+
+// EXPIRE / PEXPIRE / EXPIREAT / PEXPIREAT key time [NX | XX | GT | LT]
+pub struct Expire<'a>(&'static str, &'a [Bytes], ExpireUnit, u64, &'a Server);
+
+impl Expire<'_> {
+    async fn exec(&self) -> Result<Vec<u8>> {
+        let (name, args, unit, db_num, server) = (self.0, self.1, self.2, self.3, self.4);
+        let key = args[0].clone();
+        let Some(when) = arg_to_i64(&args[1]) else {
+            bail!(RedisError::NotInteger);
+        };
+
+        let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+        for opt in &args[2..] {
+            match opt.to_ascii_uppercase().as_slice() {
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                b"GT" => gt = true,
+                b"LT" => lt = true,
+                _ => bail!(RedisError::Other(format!(
+                    "Unsupported option {}",
+                    String::from_utf8_lossy(opt)
+                ))),
+            }
+        }
+        if nx && (xx || gt || lt) {
+            bail!(RedisError::Other(
+                "NX and XX, GT or LT options at the same time are not compatible".to_string()
+            ));
+        }
+        if gt && lt {
+            bail!(RedisError::Other(
+                "GT and LT options at the same time are not compatible".to_string()
+            ));
+        }
+
+        let now = server.clock.now_millis() as i64;
+        let invalid = || RedisError::Other(format!("invalid expire time in '{}' command", name));
+        let deadline: i64 = match unit {
+            ExpireUnit::Seconds => when.checked_mul(1000).and_then(|ms| ms.checked_add(now)),
+            ExpireUnit::Milliseconds => when.checked_add(now),
+            ExpireUnit::UnixSeconds => when.checked_mul(1000),
+            ExpireUnit::UnixMilliseconds => Some(when),
+        }
+        .ok_or_else(invalid)?;
+
+        let mut db = server.storage.lock().await;
+        let Some(kv) = lookup_key(&mut db, server, db_num, &key).await else {
+            return Ok(Integer::new(0).bytes().to_vec());
+        };
+
+        // a key without ttl has an infinite one: GT never applies to it, LT always does
+        let current = kv.expiry.map(|e| e.as_millis() as i64);
+        let apply = match current {
+            None => !xx && !gt,
+            Some(cur) => !nx && (!gt || deadline > cur) && (!lt || deadline < cur),
+        };
+        if !apply {
+            return Ok(Integer::new(0).bytes().to_vec());
+        }
+
+        // a deadline in the past deletes the key right away
+        if deadline <= now {
+            db.delete(db_num, &key).await;
+        } else {
+            db.set_expiry(db_num, &key, Some(Expiry::from_millis(deadline as u64)))
+                .await;
+        }
+        Ok(Integer::new(1).bytes().to_vec())
+    }
+}
+
+// what the TTL-like commands report
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TtlKind {
+    // TTL: remaining seconds
+    Ttl,
+    // PTTL: remaining milliseconds
+    Pttl,
+    // EXPIRETIME: unix time in seconds
+    ExpireTime,
+    // PEXPIRETIME: unix time in milliseconds
+    PExpireTime,
+}
+
+// TTL / PTTL / EXPIRETIME / PEXPIRETIME key
+// -2 when the key doesn't exist, -1 when it has no ttl
+pub struct Ttl<'a>(Bytes, TtlKind, u64, &'a Server);
+
+impl Ttl<'_> {
+    async fn exec(&self) -> Result<Vec<u8>> {
+        let mut db = self.3.storage.lock().await;
+        let now = self.3.clock.now_millis();
+        let ret = match lookup_key(&mut db, self.3, self.2, &self.0).await {
+            None => -2,
+            Some(KeyValue { expiry: None, .. }) => -1,
+            Some(KeyValue {
+                expiry: Some(e), ..
+            }) => match self.1 {
+                TtlKind::Ttl => ((e.ttl_millis(now) + 500) / 1000) as i64,
+                TtlKind::Pttl => e.ttl_millis(now) as i64,
+                TtlKind::ExpireTime => (e.as_millis() / 1000) as i64,
+                TtlKind::PExpireTime => e.as_millis() as i64,
+            },
+        };
+        Ok(Integer::new(ret).bytes().to_vec())
+    }
+}
+
+// PERSIST key: 1 when a ttl was removed
+pub struct Persist<'a>(Bytes, u64, &'a Server);
+
+impl Persist<'_> {
+    async fn exec(&self) -> Result<Vec<u8>> {
+        let mut db = self.2.storage.lock().await;
+        let removed = match lookup_key(&mut db, self.2, self.1, &self.0).await {
+            Some(KeyValue {
+                expiry: Some(_), ..
+            }) => db.set_expiry(self.1, &self.0, None).await,
+            _ => false,
+        };
+        Ok(Integer::new(removed as i64).bytes().to_vec())
+    }
+}
+
+// MOVE key db: 1 when the key was moved, 0 when it doesn't exist here or already exists there
+pub struct Move<'a>(Bytes, u64, u64, &'a Server);
+
+impl Move<'_> {
+    async fn exec(&self) -> Result<Vec<u8>> {
+        let (key, src, dst, server) = (&self.0, self.1, self.2, self.3);
+        if src == dst {
+            bail!(RedisError::Other(
+                "source and destination objects are the same".to_string()
+            ));
+        }
+        let mut db = server.storage.lock().await;
+        let Some(kv) = lookup_key(&mut db, server, src, key).await else {
+            return Ok(Integer::new(0).bytes().to_vec());
+        };
+        if lookup_key(&mut db, server, dst, key).await.is_some() {
+            return Ok(Integer::new(0).bytes().to_vec());
+        }
+        // the ttl moves along with the value
+        db.insert_or_replace(dst, key.clone(), kv).await;
+        db.delete(src, key).await;
+        Ok(Integer::new(1).bytes().to_vec())
+    }
+}
+
+// COPY source destination [DB destination-db] [REPLACE]
+// 1 when copied, 0 when the source doesn't exist or the destination does (without REPLACE)
+pub struct CopyCmd<'a>(&'a [Bytes], u64, &'a Server);
+
+impl CopyCmd<'_> {
+    async fn exec(&self) -> Result<Vec<u8>> {
+        let (args, src_db, server) = (self.0, self.1, self.2);
+        let (src, dst) = (&args[0], &args[1]);
+        let mut dst_db = src_db;
+        let mut replace = false;
+        let mut i = 2;
+        while i < args.len() {
+            match args[i].to_ascii_uppercase().as_slice() {
+                b"REPLACE" => replace = true,
+                b"DB" if i + 1 < args.len() => {
+                    i += 1;
+                    dst_db = db_index(&args[i], server)?;
+                }
+                _ => bail!(RedisError::Syntax),
+            }
+            i += 1;
+        }
+        if src_db == dst_db && src == dst {
+            bail!(RedisError::Other(
+                "source and destination objects are the same".to_string()
+            ));
+        }
+
+        let mut db = server.storage.lock().await;
+        let Some(kv) = lookup_key(&mut db, server, src_db, src).await else {
+            return Ok(Integer::new(0).bytes().to_vec());
+        };
+        if !replace && lookup_key(&mut db, server, dst_db, dst).await.is_some() {
+            return Ok(Integer::new(0).bytes().to_vec());
+        }
+        db.insert_or_replace(dst_db, dst.clone(), kv).await;
+        Ok(Integer::new(1).bytes().to_vec())
+    }
+}
+
 impl<'a> Keys<'a> {
-    pub fn new(cmd: &'a [&[u8]], rdb_file: Arc<Mutex<RdbFile>>) -> Self {
-        Keys(cmd, rdb_file)
+    pub fn new(cmd: &'a [Bytes], db_num: u64, rdb_file: Arc<Mutex<RdbFile>>) -> Self {
+        Keys(cmd, db_num, rdb_file)
     }
     async fn exec(&mut self) -> Result<Vec<u8>> {
         log::debug!("get arg is {:?}", &self.0);
         let mut ret_array = ArrayBuilder::new();
 
-        //"[$2 a*]" or "[$1 *]"
-        let (last, first) = self.0.split_last().expect("split keys args error");
-        if first[0][1] > b'1' {
-            let (star, a) = last.split_last().expect("split * from slice");
-            log::debug!(
-                "star is {} other is {:?}",
-                String::from_utf8_lossy(&[*star]),
-                a
-            );
+        //"[a*]" or "[*]"
+        let pattern = &self.0[0];
+        if pattern.as_ref() != b"*" {
+            log::debug!("pattern is {}", String::from_utf8_lossy(pattern));
             Ok(NULL_BULK_STRING.bytes().to_vec())
         } else {
-            if let Some(keys) = self.1.lock().await.keys(DB_NUM).await {
+            if let Some(keys) = self.2.lock().await.keys(self.1).await {
                 for k in keys {
-                    ret_array.insert(RespType::BulkString(BulkString::new(k.as_bytes())));
+                    ret_array.insert(RespType::BulkString(BulkString::new(&k)));
                 }
                 Ok(ret_array.build().bytes().to_vec())
             } else {
@@ -141,19 +460,19 @@ impl<'a> Keys<'a> {
     }
 }
 impl<'a> Repl<'a> {
-    pub fn new(cmd: &'a [&[u8]], s: &'a mut Server, stream: Arc<Mutex<TcpStream>>) -> Self {
+    pub fn new(cmd: &'a [Bytes], s: &'a mut Server, stream: Arc<Mutex<TcpStream>>) -> Self {
         Repl(cmd, s, stream)
     }
 
     async fn exec(&mut self) -> Result<Vec<u8>> {
         log::debug!("repl conf request is {:?}", &self.0);
 
-        match self.0[1].to_ascii_lowercase().as_slice() {
+        match self.0[0].to_ascii_lowercase().as_slice() {
             b"listening-port" => {
                 self.1
                     .insert_a_repl(Replication {
                         stream: self.2.clone(),
-                        port: String::from_utf8_lossy(self.0[3]).to_string(),
+                        port: String::from_utf8_lossy(&self.0[1]).to_string(),
                     })
                     .await;
                 Ok(SimpleString::new(b"OK").bytes().to_vec())
@@ -165,32 +484,125 @@ impl<'a> Repl<'a> {
                     Ok(SimpleString::new(b"OK").bytes().to_vec())
                 }
             }
-            _ => bail!("unknown config sub cmd "),
+            _ => bail!(RedisError::UnknownSubcommand(
+                String::from_utf8_lossy(&self.0[0]).to_string(),
+                "REPLCONF".to_string()
+            )),
         }
     }
 }
 
-#[derive(Default)]
 pub struct Config<'a> {
-    cmd: &'a [&'a [u8]],
+    cmd: &'a [Bytes],
     db_conf: Dbconf,
+    server: &'a Server,
 }
 
 impl<'a> Config<'a> {
-    pub fn new(cmd: &'a [&[u8]], db_conf: &Dbconf) -> Self {
+    pub fn new(cmd: &'a [Bytes], server: &'a Server) -> Self {
         Config {
             cmd: cmd,
-            db_conf: db_conf.clone(),
+            db_conf: server.option.db_conf.clone(),
+            server: server,
         }
     }
 
-    fn exec(&self) -> Result<Vec<u8>> {
-        log::debug!("config cmd is {:?}", &self.cmd);
-        match self.cmd[1].to_ascii_lowercase().as_slice() {
-            b"set" => {
-                todo!()
+    // CONFIG SET parameter value [parameter value ...], nothing is applied if any pair is invalid
+    async fn set(&self) -> Result<Vec<u8>> {
+        let mut save_params = None;
+        let mut rdb_compression = None;
+        let mut proto_max_bulk_len = None;
+        let mut appendonly = None;
+        let mut appendfsync = None;
+        let mut auto_rewrite_perc = None;
+        let mut auto_rewrite_min_size = None;
+        let mut aof_load_truncated = None;
+        for pair in self.cmd[1..].chunks(2) {
+            match pair[0].to_ascii_lowercase().as_slice() {
+                b"save" => {
+                    save_params = Some(persistence::parse_save_params(&arg_to_string(&pair[1]))?)
+                }
+                b"rdbcompression" => rdb_compression = Some(parse_yes_no(&pair[1], "rdbcompression")?),
+                b"appendonly" => appendonly = Some(parse_yes_no(&pair[1], "appendonly")?),
+                b"aof-load-truncated" => {
+                    aof_load_truncated = Some(parse_yes_no(&pair[1], "aof-load-truncated")?)
+                }
+                b"appendfsync" => {
+                    appendfsync = match AppendFsync::parse(&arg_to_string(&pair[1])) {
+                        Some(f) => Some(f),
+                        None => bail!(RedisError::Other(
+                            "CONFIG SET failed (possibly related to argument 'appendfsync') - argument(s) must be one of the following: always, everysec, no"
+                                .to_string()
+                        )),
+                    }
+                }
+                b"auto-aof-rewrite-percentage" => {
+                    auto_rewrite_perc = match arg_to_string(&pair[1]).parse::<u64>() {
+                        Ok(n) if n <= i32::MAX as u64 => Some(n),
+                        _ => bail!(RedisError::Other(format!(
+                            "CONFIG SET failed (possibly related to argument 'auto-aof-rewrite-percentage') - argument must be between 0 and {} inclusive",
+                            i32::MAX
+                        ))),
+                    }
+                }
+                b"auto-aof-rewrite-min-size" => {
+                    auto_rewrite_min_size = match server::parse_memory(&arg_to_string(&pair[1])) {
+                        Ok(n) if n <= i64::MAX as u64 => Some(n),
+                        _ => bail!(RedisError::Other(format!(
+                            "CONFIG SET failed (possibly related to argument 'auto-aof-rewrite-min-size') - argument must be between 0 and {} inclusive",
+                            i64::MAX
+                        ))),
+                    }
+                }
+                b"proto-max-bulk-len" => {
+                    proto_max_bulk_len = match server::parse_proto_max_bulk_len(&arg_to_string(&pair[1])) {
+                        Ok(n) => Some(n),
+                        Err(_) => bail!(RedisError::Other(format!(
+                            "CONFIG SET failed (possibly related to argument 'proto-max-bulk-len') - argument must be between {} and {} inclusive",
+                            MIN_PROTO_MAX_BULK_LEN,
+                            i64::MAX
+                        ))),
+                    }
+                }
+                _ => bail!(RedisError::Other(format!(
+                    "Unknown option or number of arguments for CONFIG SET - '{}'",
+                    String::from_utf8_lossy(&pair[0])
+                ))),
             }
-            b"get" => match self.cmd[3].to_ascii_lowercase().as_slice() {
+        }
+        if let Some(params) = save_params {
+            self.server.persistence.set_save_params(params);
+        }
+        if let Some(enabled) = rdb_compression {
+            self.server.persistence.set_rdb_compression(enabled);
+        }
+        if let Some(n) = proto_max_bulk_len {
+            self.server.proto_max_bulk_len.store(n, Ordering::Relaxed);
+        }
+        if let Some(fsync) = appendfsync {
+            self.server.aof.set_fsync(fsync);
+        }
+        if let Some(perc) = auto_rewrite_perc {
+            self.server.aof.set_auto_rewrite_perc(perc);
+        }
+        if let Some(size) = auto_rewrite_min_size {
+            self.server.aof.set_auto_rewrite_min_size(size);
+        }
+        if let Some(enabled) = aof_load_truncated {
+            self.server.aof.set_load_truncated(enabled);
+        }
+        if let Some(enabled) = appendonly {
+            aof::set_enabled(self.server, enabled).await?;
+        }
+        Ok(SimpleString::new(b"OK").bytes().to_vec())
+    }
+
+    async fn exec(&self) -> Result<Vec<u8>> {
+        log::debug!("config cmd is {:?}", &self.cmd);
+        match self.cmd[0].to_ascii_lowercase().as_slice() {
+            b"set" if self.cmd.len() >= 3 && self.cmd.len() % 2 == 1 => self.set().await,
+            b"set" => bail!(RedisError::WrongArity("config|set".to_string())),
+            b"get" if self.cmd.len() == 2 => match self.cmd[1].to_ascii_lowercase().as_slice() {
                 b"dir" => {
                     let mut ret = ArrayBuilder::new();
                     ret.insert(RespType::BulkString(BulkString::new(b"dir")));
@@ -207,9 +619,154 @@ impl<'a> Config<'a> {
                     )));
                     Ok(ret.build().bytes().to_vec())
                 }
+                b"save" => {
+                    let mut ret = ArrayBuilder::new();
+                    ret.insert(RespType::BulkString(BulkString::new(b"save")));
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        persistence::format_save_params(&self.server.persistence.save_params())
+                            .as_bytes(),
+                    )));
+                    Ok(ret.build().bytes().to_vec())
+                }
+                b"rdbcompression" => {
+                    let mut ret = ArrayBuilder::new();
+                    ret.insert(RespType::BulkString(BulkString::new(b"rdbcompression")));
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        if self.server.persistence.rdb_compression() {
+                            b"yes"
+                        } else {
+                            b"no"
+                        },
+                    )));
+                    Ok(ret.build().bytes().to_vec())
+                }
+                b"proto-max-bulk-len" => {
+                    let mut ret = ArrayBuilder::new();
+                    ret.insert(RespType::BulkString(BulkString::new(b"proto-max-bulk-len")));
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        self.server
+                            .proto_max_bulk_len
+                            .load(Ordering::Relaxed)
+                            .to_string()
+                            .as_bytes(),
+                    )));
+                    Ok(ret.build().bytes().to_vec())
+                }
+                b"appendonly" => {
+                    let mut ret = ArrayBuilder::new();
+                    ret.insert(RespType::BulkString(BulkString::new(b"appendonly")));
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        if self.server.aof.enabled() {
+                            b"yes"
+                        } else {
+                            b"no"
+                        },
+                    )));
+                    Ok(ret.build().bytes().to_vec())
+                }
+                b"appendfsync" => {
+                    let mut ret = ArrayBuilder::new();
+                    ret.insert(RespType::BulkString(BulkString::new(b"appendfsync")));
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        self.server.aof.fsync().to_string().as_bytes(),
+                    )));
+                    Ok(ret.build().bytes().to_vec())
+                }
+                b"appendfilename" => {
+                    let mut ret = ArrayBuilder::new();
+                    ret.insert(RespType::BulkString(BulkString::new(b"appendfilename")));
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        self.server.option.appendfilename.as_bytes(),
+                    )));
+                    Ok(ret.build().bytes().to_vec())
+                }
+                b"aof-load-truncated" => {
+                    let mut ret = ArrayBuilder::new();
+                    ret.insert(RespType::BulkString(BulkString::new(b"aof-load-truncated")));
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        if self.server.aof.load_truncated() {
+                            b"yes"
+                        } else {
+                            b"no"
+                        },
+                    )));
+                    Ok(ret.build().bytes().to_vec())
+                }
+                b"appenddirname" => {
+                    let mut ret = ArrayBuilder::new();
+                    ret.insert(RespType::BulkString(BulkString::new(b"appenddirname")));
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        self.server.option.appenddirname.as_bytes(),
+                    )));
+                    Ok(ret.build().bytes().to_vec())
+                }
+                b"auto-aof-rewrite-percentage" => {
+                    let mut ret = ArrayBuilder::new();
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        b"auto-aof-rewrite-percentage",
+                    )));
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        self.server.aof.auto_rewrite_perc().to_string().as_bytes(),
+                    )));
+                    Ok(ret.build().bytes().to_vec())
+                }
+                b"auto-aof-rewrite-min-size" => {
+                    let mut ret = ArrayBuilder::new();
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        b"auto-aof-rewrite-min-size",
+                    )));
+                    ret.insert(RespType::BulkString(BulkString::new(
+                        self.server
+                            .aof
+                            .auto_rewrite_min_size()
+                            .to_string()
+                            .as_bytes(),
+                    )));
+                    Ok(ret.build().bytes().to_vec())
+                }
                 _ => Ok(Error::new(b"1").bytes().to_vec()),
             },
-            _ => bail!("unknown config sub cmd "),
+            b"get" => bail!(RedisError::WrongArity("config|get".to_string())),
+            _ => bail!(RedisError::UnknownSubcommand(
+                String::from_utf8_lossy(&self.cmd[0]).to_string(),
+                "CONFIG".to_string()
+            )),
+        }
+    }
+}
+
+// DEBUG SLEEP seconds | DEBUG SET-ACTIVE-EXPIRE 0|1
+pub struct DebugCmd<'a>(&'a [Bytes], &'a Server);
+
+impl DebugCmd<'_> {
+    async fn exec(&self) -> Result<Vec<u8>> {
+        let sub = self.0[0].to_ascii_lowercase();
+        match (sub.as_slice(), self.0.len()) {
+            (b"sleep", 2) => {
+                // negative, NaN, inf and values too large for a Duration are all rejected
+                let duration = std::str::from_utf8(&self.0[1])
+                    .ok()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+                let Some(duration) = duration else {
+                    bail!(RedisError::Other("value is not a valid float".to_string()));
+                };
+                // only this connection waits, other clients are served meanwhile
+                self.1.clock.sleep(duration).await;
+                Ok(SimpleString::new(b"OK").bytes().to_vec())
+            }
+            (b"set-active-expire", 2) => {
+                let enabled = match arg_to_i64(&self.0[1]) {
+                    Some(n) => n != 0,
+                    None => bail!(RedisError::NotInteger),
+                };
+                self.1.active_expire.store(enabled, Ordering::Relaxed);
+                Ok(SimpleString::new(b"OK").bytes().to_vec())
+            }
+            _ => bail!(RedisError::UnknownSubcommand(
+                String::from_utf8_lossy(&self.0[0]).to_string(),
+                "DEBUG".to_string()
+            )),
         }
     }
 }
@@ -232,10 +789,12 @@ impl<'a> Info<'a> {
         let mut all = String::new();
         match &self.key {
             Some(k) => {
-                let v_hashmap = self.server.get_a_info(k).await;
-                v_hashmap
-                    .iter()
-                    .for_each(|e| all.push_str(format!("{}:{}\r\n", e.key(), e.value()).as_str()));
+                // an unknown section is an empty reply
+                if let Some(v_hashmap) = self.server.get_a_info(k).await {
+                    v_hashmap.iter().for_each(|e| {
+                        all.push_str(format!("{}:{}\r\n", e.key(), e.value()).as_str())
+                    });
+                }
             }
             //inter for all keys
             None => {
@@ -256,146 +815,284 @@ impl<'a> Info<'a> {
         }
     }
 }
+// COMMAND [COUNT | INFO [name ...] | DOCS [name ...] | GETKEYS cmd [arg ...]]
+pub struct Command<'a>(&'a [Bytes]);
+
+impl<'a> Command<'a> {
+    pub fn new(args: &'a [Bytes]) -> Self {
+        Command(args)
+    }
+
+    fn exec(&self) -> Result<Vec<u8>> {
+        let Some(sub) = self.0.first() else {
+            return Ok(Command::info_of(COMMAND_TABLE.iter().map(Some)));
+        };
+        let ret = match sub.to_ascii_lowercase().as_slice() {
+            b"count" if self.0.len() == 1 => Integer::new(COMMAND_TABLE.len() as i64)
+                .bytes()
+                .to_vec(),
+            b"info" if self.0.len() == 1 => Command::info_of(COMMAND_TABLE.iter().map(Some)),
+            b"info" => Command::info_of(self.0[1..].iter().map(|n| command_table::lookup(n))),
+            b"docs" => {
+                let mut ret = ArrayBuilder::new();
+                let specs: Vec<&CommandSpec> = if self.0.len() == 1 {
+                    COMMAND_TABLE.iter().collect()
+                } else {
+                    self.0[1..].iter().filter_map(|n| command_table::lookup(n)).collect()
+                };
+                for spec in specs {
+                    ret.insert(RespType::BulkString(BulkString::new(spec.name.as_bytes())))
+                        .insert(RespType::Array(spec.docs()));
+                }
+                ret.build().bytes().to_vec()
+            }
+            b"getkeys" if self.0.len() >= 2 => self.getkeys(&self.0[1..]),
+            _ => Error::new(
+                format!(
+                    "ERR unknown subcommand or wrong number of arguments for '{}'. Try COMMAND HELP.",
+                    String::from_utf8_lossy(sub)
+                )
+                .as_bytes(),
+            )
+            .bytes()
+            .to_vec(),
+        };
+        Ok(ret)
+    }
+
+    // unknown names are reported as nil entries
+    fn info_of(specs: impl Iterator<Item = Option<&'static CommandSpec>>) -> Vec<u8> {
+        let mut ret = ArrayBuilder::new();
+        for spec in specs {
+            match spec {
+                Some(spec) => ret.insert(RespType::Array(spec.info())),
+                None => ret.insert(RespType::BulkString(NULL_BULK_STRING)),
+            };
+        }
+        ret.build().bytes().to_vec()
+    }
+
+    fn getkeys(&self, cmd: &[Bytes]) -> Vec<u8> {
+        let Some(spec) = command_table::lookup(&cmd[0]) else {
+            return Error::new(b"ERR Invalid command specified")
+                .bytes()
+                .to_vec();
+        };
+        if !spec.check_arity(cmd.len()) {
+            return Error::new(b"ERR Invalid number of arguments specified for command")
+                .bytes()
+                .to_vec();
+        }
+        let keys = spec.get_keys(cmd);
+        if keys.is_empty() {
+            return Error::new(b"ERR The command has no key arguments")
+                .bytes()
+                .to_vec();
+        }
+        let mut ret = ArrayBuilder::new();
+        for k in keys {
+            ret.insert(RespType::BulkString(BulkString::new(k)));
+        }
+        ret.build().bytes().to_vec()
+    }
+}
+
+fn arg_to_string(arg: &Bytes) -> String {
+    String::from_utf8_lossy(arg).to_string()
+}
+
+// a yes/no config value
+fn parse_yes_no(arg: &Bytes, name: &str) -> Result<bool> {
+    match arg.to_ascii_lowercase().as_slice() {
+        b"yes" => Ok(true),
+        b"no" => Ok(false),
+        _ => bail!(RedisError::Other(format!(
+            "CONFIG SET failed (possibly related to argument '{}') - argument must be 'yes' or 'no'",
+            name
+        ))),
+    }
+}
+
+fn arg_to_i64(arg: &Bytes) -> Option<i64> {
+    std::str::from_utf8(arg).ok().and_then(|s| s.parse().ok())
+}
+
+// a database index given to SELECT, MOVE or COPY ... DB
+fn db_index(arg: &Bytes, server: &Server) -> Result<u64> {
+    match arg_to_i64(arg) {
+        Some(n) => check_db_index(n, server),
+        None => bail!(RedisError::NotInteger),
+    }
+}
+
+fn check_db_index(n: i64, server: &Server) -> Result<u64> {
+    if n < 0 || n as u64 >= server.option.databases {
+        bail!(RedisError::Other("DB index is out of range".to_string()));
+    }
+    Ok(n as u64)
+}
+
+// state of one connection, kept across its commands
+#[derive(Debug, Default)]
+pub struct ClientState {
+    // the database selected with SELECT
+    pub db: u64,
+}
+
+// args[0] is the command name, the rest are its arguments
+// `stream_arc` is the client connection, None when replaying the append-only file
 pub async fn from_cmd_to_exec(
-    s: Vec<&[u8]>,
-    arg_len: u8,
-    stream_arc: Arc<Mutex<TcpStream>>,
+    args: Vec<Bytes>,
+    stream_arc: Option<Arc<Mutex<TcpStream>>>,
     server: &mut Server,
+    client: &mut ClientState,
 ) -> Result<Vec<u8>> {
-    log::debug!("get s:{:?}", s);
-    let output = match s[1].to_ascii_lowercase().as_slice() {
-        b"ping" => crate::commands::Ping.exec(),
-        b"echo" => {
-            // 计算echo后的字符串长度,for create vec
-            let len = s[2..].iter().enumerate().fold(
-                0,
-                |s, (i, &v)| {
-                    if i % 2 != 0 {
-                        s + v.len()
-                    } else {
-                        s
-                    }
-                },
-            );
-            let mut ret = Vec::with_capacity(len);
-            log::debug!("s len is {}", len);
-            s[2..].iter().enumerate().for_each(|(i, v)| {
-                if i % 2 != 0 {
-                    ret.extend_from_slice(v);
-                }
-            });
-            crate::commands::Echo(BulkString::new(ret.as_slice())).exec()
-        }
-        b"get" => {
-            let (_, l) = s[2]
-                .split_first()
-                .expect("get arg's length  $n split error");
-            if s[3].len()
-                == String::from_utf8(l.to_vec())
-                    .expect("get arg size to utf8 error")
-                    .parse()
-                    .expect("get arg size parse error")
-            {
-                log::debug!("get arg size is {} ", s[3].len());
-            }
-            crate::commands::Get(
-                String::from_utf8(s[3].to_vec()).expect("convert get arg to string"),
-                &server,
+    log::debug!("get args:{:?}", args);
+    let Some(spec) = command_table::lookup(&args[0]) else {
+        bail!(RedisError::unknown_command(&args));
+    };
+    if !spec.check_arity(args.len()) {
+        bail!(RedisError::WrongArity(spec.name.to_string()));
+    }
+    if !spec.is_write() {
+        return exec_command(spec, &args, stream_arc, server, client).await;
+    }
+
+    // write commands run one at a time, the ones that changed the dataset are appended
+    // to the AOF in the order they were applied
+    let aof = server.aof.clone();
+    let _writes = aof.write_lock.lock().await;
+    // like Redis, no writes while the AOF can't be written, the client would believe them
+    // stored; aof::run clears the error once the write is retried successfully
+    if let Some(e) = aof.write_error() {
+        bail!(RedisError::AofWrite(e));
+    }
+    // the final snapshot is already written
+    if server.is_shutting_down() {
+        bail!(RedisError::Other("the server is shutting down".to_string()));
+    }
+    let changes = server.storage.lock().await.changes();
+    let output = exec_command(spec, &args, stream_arc, server, client).await?;
+    if server.storage.lock().await.changes() != changes {
+        if let Err(e) = aof::feed(server, client.db, &args).await {
+            error!("{:#}", e);
+            bail!(RedisError::AofWrite(format!("{:#}", e)));
+        }
+    }
+    Ok(output)
+}
+
+async fn exec_command(
+    spec: &CommandSpec,
+    args: &[Bytes],
+    stream_arc: Option<Arc<Mutex<TcpStream>>>,
+    server: &mut Server,
+    client: &mut ClientState,
+) -> Result<Vec<u8>> {
+    let name = spec.name;
+    let output = match name {
+        "ping" => match args.len() {
+            1 => crate::commands::Ping.exec(),
+            2 => crate::commands::Echo(BulkString::new(&args[1])).exec(),
+            _ => bail!(RedisError::WrongArity(name.to_string())),
+        },
+        "echo" => crate::commands::Echo(BulkString::new(&args[1])).exec(),
+        "get" => {
+            crate::commands::Get(args[1].clone(), client.db, server)
+                .exec()
+                .await
+        }
+        "set" => {
+            let opts = SetOptions::parse(&args[3..], server.clock.now_millis())?;
+            crate::commands::Set(
+                args[1].clone(),
+                RedisValue::String(args[2].clone()),
+                opts,
+                client.db,
+                server,
             )
             .exec()
             .await
         }
-        b"set" => match arg_len {
-            3 => {
-                crate::commands::Set(
-                    String::from_utf8(s[3].to_vec()).expect("convert get arg to string"),
-                    KeyValue {
-                        value: RedisValue::String(
-                            String::from_utf8(s[5].to_vec()).expect("convert get arg to string"),
-                        ),
-                        expiry: None,
-                    },
-                    &server,
-                )
+        "getset" => {
+            crate::commands::Set(
+                args[1].clone(),
+                RedisValue::String(args[2].clone()),
+                SetOptions::getset(),
+                client.db,
+                server,
+            )
+            .exec()
+            .await
+        }
+        "save" => {
+            persistence::save(server).await?;
+            Ok(SimpleString::new(b"OK").bytes().to_vec())
+        }
+        "bgsave" => {
+            if args.len() > 2 || (args.len() == 2 && !args[1].eq_ignore_ascii_case(b"schedule")) {
+                bail!(RedisError::Syntax);
+            }
+            persistence::bgsave(server).await?;
+            Ok(SimpleString::new(b"Background saving started")
+                .bytes()
+                .to_vec())
+        }
+        "bgrewriteaof" => {
+            aof::bgrewrite(server).await?;
+            Ok(
+                SimpleString::new(b"Background append only file rewriting started")
+                    .bytes()
+                    .to_vec(),
+            )
+        }
+        "lastsave" => Ok(Integer::new(server.persistence.last_save() as i64)
+            .bytes()
+            .to_vec()),
+        "shutdown" => {
+            let mode = match args.get(1).map(|a| a.to_ascii_uppercase()) {
+                None => ShutdownMode::Default,
+                Some(m) if args.len() == 2 && m == b"SAVE" => ShutdownMode::Save,
+                Some(m) if args.len() == 2 && m == b"NOSAVE" => ShutdownMode::NoSave,
+                _ => bail!(RedisError::Syntax),
+            };
+            // no reply when it worked, the connection is closed once the server is shutting down
+            server.shutdown(mode).await?;
+            Ok(Vec::new())
+        }
+        "debug" => DebugCmd(&args[1..], server).exec().await,
+        "config" => Config::new(&args[1..], server).exec().await,
+        "keys" => {
+            Keys::new(&args[1..], client.db, Arc::clone(&server.storage))
                 .exec()
                 .await
-            }
-            5 => {
-                let time_num = String::from_utf8(s[9].to_vec())
-                    .expect("conver time to str ")
-                    .parse::<u64>()
-                    .expect("parse time num error");
-                log::debug!(" set time is {}", time_num);
-
-                match s[7] {
-                    b"px" => {
-                        crate::commands::Set(
-                            String::from_utf8(s[3].to_vec()).expect("convert get arg to string"),
-                            KeyValue {
-                                value: RedisValue::String(
-                                    String::from_utf8(s[5].to_vec())
-                                        .expect("convert get arg to string"),
-                                ),
-                                expiry: Some(Expiry::Milliseconds(
-                                    SystemTime::now()
-                                        .duration_since(UNIX_EPOCH)
-                                        .expect("get now timestamp error")
-                                        .as_millis() as u64
-                                        + time_num,
-                                )),
-                            },
-                            &server,
-                        )
-                        .exec()
-                        .await
-                    }
-                    b"ex" => {
-                        crate::commands::Set(
-                            String::from_utf8(s[3].to_vec()).expect("convert get arg to string"),
-                            KeyValue {
-                                value: RedisValue::String(
-                                    String::from_utf8(s[5].to_vec())
-                                        .expect("convert get arg to string"),
-                                ),
-                                expiry: Some(Expiry::Seconds(
-                                    (SystemTime::now()
-                                        .duration_since(UNIX_EPOCH)
-                                        .expect("get now timestamp error")
-                                        .as_secs()
-                                        + time_num) as u32,
-                                )),
-                            },
-                            &server,
-                        )
-                        .exec()
-                        .await
-                    }
-                    _ => bail!("expirty arg is error"),
-                }
-            }
-            _ => {
-                error!("set arg is error");
-                Ok(BulkString::new(b"-1").bytes().to_vec())
-            }
-        },
-        b"config" => Config::new(&s[2..], &server.option.db_conf).exec(),
-        b"keys" => Keys::new(&s[2..], Arc::clone(&server.storage)).exec().await,
-        b"info" => match arg_len {
-            2 => Info::new(None, &server).exec().await,
-            3 => {
-                Info::new(Some(String::from_utf8_lossy(&s[2]).to_uppercase()), &server)
+        }
+        "info" => match args.len() {
+            1 => Info::new(None, server).exec().await,
+            2 => {
+                Info::new(Some(arg_to_string(&args[1]).to_lowercase()), server)
                     .exec()
                     .await
             }
-            _ => bail!("info args number error!"),
+            _ => bail!(RedisError::Syntax),
         },
-        b"replconf" => Repl::new(&s[2..], server, stream_arc.clone()).exec().await,
-        b"psync" => {
-            log::debug!("pysync is {:?}", &s[2..]);
+        "replconf" => {
+            if args.len() < 3 {
+                bail!(RedisError::WrongArity(name.to_string()));
+            }
+            let Some(stream) = stream_arc else {
+                bail!(RedisError::Other(
+                    "REPLCONF needs a client connection".to_string()
+                ));
+            };
+            Repl::new(&args[1..], server, stream).exec().await
+        }
+        "psync" => {
+            log::debug!("pysync is {:?}", &args[1..]);
 
-            match s[3].to_ascii_lowercase().as_slice() {
+            match args[1].as_ref() {
                 b"?" => {
-                    if s[5] == b"-1" {
+                    if args[2].as_ref() == b"-1" {
                         let mut ret = Vec::new();
                         ret.extend(
                             SimpleString::new(
@@ -421,11 +1118,57 @@ pub async fn from_cmd_to_exec(
                         Ok(SimpleString::new(b"-1").bytes().to_vec())
                     }
                 }
-                _ => bail!("match psync error!"),
+                _ => bail!(RedisError::Other(
+                    "partial resynchronization is not supported".to_string()
+                )),
             }
         }
 
-        _ => bail!("cmd parse error"),
+        "expire" | "pexpire" | "expireat" | "pexpireat" => {
+            let unit = match name {
+                "expire" => ExpireUnit::Seconds,
+                "pexpire" => ExpireUnit::Milliseconds,
+                "expireat" => ExpireUnit::UnixSeconds,
+                _ => ExpireUnit::UnixMilliseconds,
+            };
+            Expire(name, &args[1..], unit, client.db, server)
+                .exec()
+                .await
+        }
+        "ttl" | "pttl" | "expiretime" | "pexpiretime" => {
+            let kind = match name {
+                "ttl" => TtlKind::Ttl,
+                "pttl" => TtlKind::Pttl,
+                "expiretime" => TtlKind::ExpireTime,
+                _ => TtlKind::PExpireTime,
+            };
+            Ttl(args[1].clone(), kind, client.db, server).exec().await
+        }
+        "persist" => Persist(args[1].clone(), client.db, server).exec().await,
+        "dbsize" => Ok(
+            Integer::new(server.storage.lock().await.dbsize(client.db).await as i64)
+                .bytes()
+                .to_vec(),
+        ),
+        "select" => {
+            client.db = db_index(&args[1], server)?;
+            Ok(SimpleString::new(b"OK").bytes().to_vec())
+        }
+        "swapdb" => {
+            let invalid = |which: &str| RedisError::Other(format!("invalid {} DB index", which));
+            let a = arg_to_i64(&args[1]).ok_or_else(|| invalid("first"))?;
+            let b = arg_to_i64(&args[2]).ok_or_else(|| invalid("second"))?;
+            let (a, b) = (check_db_index(a, server)?, check_db_index(b, server)?);
+            server.storage.lock().await.swap_db(a, b);
+            Ok(SimpleString::new(b"OK").bytes().to_vec())
+        }
+        "move" => {
+            let dst = db_index(&args[2], server)?;
+            Move(args[1].clone(), client.db, dst, server).exec().await
+        }
+        "copy" => CopyCmd(&args[1..], client.db, server).exec().await,
+        "command" => Command::new(&args[1..]).exec(),
+        _ => bail!(RedisError::unknown_command(args)),
     };
 
     output
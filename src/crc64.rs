@@ -0,0 +1,51 @@
+// CRC-64/Jones as used by Redis for RDB checksums: reflected polynomial
+// 0xad93d23594c935a9, initial value 0, no final xor.
+// crc64("123456789") == 0xe9c6d914c4b8d9ca
+
+const POLY: u64 = 0x95ac9329ac4bc9b5; // 0xad93d23594c935a9 bit-reversed
+
+const TABLE: [u64; 256] = make_table();
+
+const fn make_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+// running checksum, fed with `write` as bytes are read or written
+#[derive(Debug, Clone, Default)]
+pub struct Digest {
+    crc: u64,
+}
+
+impl Digest {
+    pub fn new() -> Self {
+        Digest { crc: 0 }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        let mut crc = self.crc;
+        for b in bytes {
+            crc = TABLE[((crc ^ *b as u64) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.crc = crc;
+    }
+
+    pub fn sum64(&self) -> u64 {
+        self.crc
+    }
+}
@@ -1,5 +1,13 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use bytes::Bytes;
 use dashmap::DashMap;
 
+use crate::clock::{Clock, SystemClock};
+
 #[derive(Debug, Clone, Default)]
 pub struct Dbconf {
     dir: String,
@@ -35,6 +43,56 @@ const TYPE_SELECTDB: u8 = 0xFE;
 const TYPE_EOF: u8 = 0xFF;
 const TYPE_EXPIRETIME: u8 = 0xFD;
 const TYPE_EXPIRETIME_MS: u8 = 0xFC;
+// 不支持、加载时跳过的操作码：函数库、模块辅助数据、键的LRU空闲时间和LFU计数
+const TYPE_FUNCTION2: u8 = 0xF5;
+const TYPE_MODULE_AUX: u8 = 0xF7;
+const TYPE_IDLE: u8 = 0xF8;
+const TYPE_FREQ: u8 = 0xF9;
+// RESIZEDB提示最多预留这么多键
+const MAX_RESIZEDB_HINT: u64 = 1 << 20;
+
+// RDB中的值类型
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+// 紧凑编码的值类型，值本身是一个字符串编码的二进制块
+const RDB_TYPE_HASH_ZIPMAP: u8 = 9;
+const RDB_TYPE_LIST_ZIPLIST: u8 = 10;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_ZSET_ZIPLIST: u8 = 12;
+const RDB_TYPE_HASH_ZIPLIST: u8 = 13;
+const RDB_TYPE_LIST_QUICKLIST: u8 = 14;
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+// 字符串的特殊编码(长度编码前两位为11时的低6位)
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+// 模块数据中每个值前的操作码
+const RDB_MODULE_OPCODE_EOF: u64 = 0;
+const RDB_MODULE_OPCODE_SINT: u64 = 1;
+const RDB_MODULE_OPCODE_UINT: u64 = 2;
+const RDB_MODULE_OPCODE_FLOAT: u64 = 3;
+const RDB_MODULE_OPCODE_DOUBLE: u64 = 4;
+const RDB_MODULE_OPCODE_STRING: u64 = 5;
+
+// quicklist2节点的容器类型
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+// 写入RDB辅助字段redis-ver的版本
+const REDIS_VER: &str = "7.2.0";
 
 // DB number for test
 pub const DB_NUM: u64 = 0;
@@ -57,11 +115,40 @@ pub enum RdbVType {
     Module2,
     StreamListPacks,
 }
-// 过期时间类型
-#[derive(Debug, Clone, PartialEq)]
-pub enum Expiry {
-    Seconds(u32),
-    Milliseconds(u64),
+// 过期时间：绝对时间戳(ms)
+// RDB中的秒级(0xFD)和毫秒级(0xFC)过期时间在加载时都转换为毫秒
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Expiry(u64);
+
+impl KeyValue {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expiry.as_ref().is_some_and(|e| e.is_expired(now))
+    }
+}
+
+impl Expiry {
+    pub fn from_millis(ms: u64) -> Self {
+        Expiry(ms)
+    }
+
+    pub fn from_secs(secs: u64) -> Self {
+        Expiry(secs.saturating_mul(1000))
+    }
+
+    // 以毫秒表示的过期时间
+    pub fn as_millis(&self) -> u64 {
+        self.0
+    }
+
+    // 在时间now(ms)是否已经过期
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.0 < now
+    }
+
+    // 距时间now(ms)的剩余时间，已过期时为0
+    pub fn ttl_millis(&self, now: u64) -> u64 {
+        self.0.saturating_sub(now)
+    }
 }
 
 // 支持过期时间的键值对
@@ -75,11 +162,11 @@ pub struct KeyValue {
 // Redis支持的数据结构
 #[derive(Debug, Clone)]
 pub enum RedisValue {
-    String(String),
-    List(Vec<String>),
-    Set(Vec<String>),
-    SortedSet(Vec<(String, f64)>),
-    Hash(Vec<(String, String)>),
+    String(Bytes),
+    List(Vec<Bytes>),
+    Set(Vec<Bytes>),
+    SortedSet(Vec<(Bytes, f64)>),
+    Hash(Vec<(Bytes, Bytes)>),
     // Zipmap(Vec<(String, String)>),
     // Ziplist(Vec<Vec<u8>>),
     // SetInts(Vec<i64>),
@@ -90,6 +177,19 @@ pub enum RedisValue {
     // StreamListPacks(Vec<u8>),
 }
 
+impl RedisValue {
+    // TYPE命令返回的类型名
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RedisValue::String(_) => "string",
+            RedisValue::List(_) => "list",
+            RedisValue::Set(_) => "set",
+            RedisValue::SortedSet(_) => "zset",
+            RedisValue::Hash(_) => "hash",
+        }
+    }
+}
+
 // RDB file structure
 /*
 // ----------------------------#
@@ -131,7 +231,50 @@ FF                          ## End of RDB file indicator
 pub struct RdbFile {
     pub version: u32,
     pub aux_fields: DashMap<String, String>,
-    pub databases: DashMap<u64, DashMap<String, KeyValue>>,
+    pub databases: DashMap<u64, DashMap<Bytes, KeyValue>>,
+    // 每个数据库带过期时间的键，与 databases 中的 KeyValue.expiry 保持一致
+    expires: DashMap<u64, ExpiresIndex>,
+    // 判断过期用的时钟，由Server通过set_clock共享
+    clock: Arc<dyn Clock>,
+    // 上次保存后的修改次数
+    dirty: u64,
+    // 命令产生的修改总数，不会清零，AOF据此判断一条命令是否修改了数据
+    changes: u64,
+}
+
+// 单个数据库的过期索引：键 -> 过期时间(ms)，同时按过期时间排序
+#[derive(Debug, Clone, Default)]
+pub struct ExpiresIndex {
+    by_key: HashMap<Bytes, u64>,
+    by_deadline: BTreeSet<(u64, Bytes)>,
+}
+
+impl ExpiresIndex {
+    pub fn set(&mut self, key: Bytes, deadline: u64) {
+        if let Some(old) = self.by_key.insert(key.clone(), deadline) {
+            self.by_deadline.remove(&(old, key.clone()));
+        }
+        self.by_deadline.insert((deadline, key));
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<u64> {
+        let (key, old) = self.by_key.remove_entry(key)?;
+        self.by_deadline.remove(&(old, key));
+        Some(old)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<u64> {
+        self.by_key.get(key).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_key.len()
+    }
+
+    // 最早过期的count个键
+    pub fn earliest(&self, count: usize) -> Vec<(u64, Bytes)> {
+        self.by_deadline.iter().take(count).cloned().collect()
+    }
 }
 
 impl RdbFile {
@@ -141,60 +284,133 @@ impl RdbFile {
             version: version,
             aux_fields: DashMap::new(),
             databases: DashMap::new(),
+            expires: DashMap::new(),
+            clock: Arc::new(SystemClock::new()),
+            dirty: 0,
+            changes: 0,
         }
     }
 
-    pub fn set_capacity(&mut self, size: usize) -> Result<()> {
-        self.databases
-            .try_reserve(size)
-            .expect("try to set capacity error ");
-        Ok(())
+    // 上次保存后的修改次数
+    pub fn dirty(&self) -> u64 {
+        self.dirty
+    }
+
+    // 保存成功后减去保存开始时的修改次数，保存期间的新修改仍然保留
+    pub fn clear_dirty(&mut self, saved: u64) {
+        self.dirty = self.dirty.saturating_sub(saved);
+    }
+
+    // 命令产生的修改总数(过期删除不计入)
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // 当前时间(ms)
+    pub fn now_millis(&self) -> u64 {
+        self.clock.now_millis()
+    }
+
+    // 按RESIZEDB的提示为数据库db的键空间预留容量。提示只是优化：
+    // 损坏或恶意的文件可能给出极大的值，所以截断到MAX_RESIZEDB_HINT，分配失败时忽略
+    pub fn set_capacity(&mut self, db: u64, size: u64) {
+        let size = size.min(MAX_RESIZEDB_HINT) as usize;
+        if let Some(mut keys) = self.databases.get_mut(&db) {
+            let _ = keys.try_reserve(size);
+        }
     }
 
     // 异步获取指定数据库中的键值对
-    pub async fn get(&self, db: u64, key: &str) -> Option<KeyValue> {
+    pub async fn get(&self, db: u64, key: &[u8]) -> Option<KeyValue> {
         log::debug!("database is {:?} db_num is {}", self.databases, db);
-        log::debug!(
-            "get debug :{:?}",
-            self.databases.get(&db).expect("get db error").get(key)
-        );
-        match self.databases.get(&db).expect("get db error").get(key) {
-            Some(v) => Some(v.value().clone()),
-            None => None,
-        }
+        let value = self.databases.get(&db)?.get(key).map(|v| v.value().clone());
+        log::debug!("get debug :{:?}", value);
+        value
+    }
+
+    // 获取未过期的键值对，已过期的键视为不存在
+    pub async fn get_live(&self, db: u64, key: &[u8]) -> Option<KeyValue> {
+        let now = self.now_millis();
+        self.get(db, key).await.filter(|kv| !kv.is_expired(now))
     }
 
-    // 异步设置键值对，如果已存在则更新
-    pub async fn insert(
+    // 插入或替换键值对，返回旧值（已过期的旧值视为不存在）
+    pub async fn insert_or_replace(
         &mut self,
         db: u64,
-        key: String,
-        value: RedisValue,
-        expiry: Option<Expiry>,
-    ) {
+        key: Bytes,
+        kv: KeyValue,
+    ) -> Option<KeyValue> {
+        log::debug!("insert debug :{:?} {:?}", key, kv);
+        let now = self.now_millis();
+        self.dirty += 1;
+        self.changes += 1;
+        {
+            let mut index = self.expires.entry(db).or_default();
+            match &kv.expiry {
+                Some(e) => index.set(key.clone(), e.as_millis()),
+                None => {
+                    index.remove(&key);
+                }
+            }
+        }
         self.databases
             .entry(db)
             .or_insert(DashMap::new())
-            .entry(key.clone())
-            .or_insert(KeyValue {
-                value: value,
-                expiry: expiry,
-            });
-        log::debug!(
-            "insert debug :{:?}",
-            self.databases
-                .get(&DB_NUM)
-                .expect("get db error")
-                .get(&key)
-                .expect("get key error")
-                .value()
-        )
+            .insert(key, kv)
+            .filter(|old| !old.is_expired(now))
+    }
+
+    // 仅当键不存在时插入；键已存在时不写入并返回现有值
+    pub async fn insert_if_absent(
+        &mut self,
+        db: u64,
+        key: Bytes,
+        kv: KeyValue,
+    ) -> Option<KeyValue> {
+        if let Some(old) = self.get_live(db, &key).await {
+            return Some(old);
+        }
+        self.insert_or_replace(db, key, kv).await;
+        None
+    }
+
+    // 仅当键存在时更新；返回旧值，键不存在时不写入并返回None
+    pub async fn update_if_present(
+        &mut self,
+        db: u64,
+        key: Bytes,
+        kv: KeyValue,
+    ) -> Option<KeyValue> {
+        self.get_live(db, &key).await?;
+        self.insert_or_replace(db, key, kv).await
     }
 
     // 异步删除指定的键
-    pub async fn delete(&mut self, db: u64, key: &str) -> bool {
+    pub async fn delete(&mut self, db: u64, key: &[u8]) -> bool {
+        let deleted = self.remove(db, key);
+        if deleted {
+            self.changes += 1;
+        }
+        deleted
+    }
+
+    // 删除已过期的键，不算作命令产生的修改
+    pub async fn delete_expired(&mut self, db: u64, key: &[u8]) -> bool {
+        self.remove(db, key)
+    }
+
+    fn remove(&mut self, db: u64, key: &[u8]) -> bool {
+        if let Some(mut index) = self.expires.get_mut(&db) {
+            index.remove(key);
+        }
         if let Some(db_entry) = self.databases.get_mut(&db) {
             if let Some((k, _)) = db_entry.remove(key) {
+                self.dirty += 1;
                 if k == key {
                     return true;
                 }
@@ -203,8 +419,86 @@ impl RdbFile {
         false
     }
 
+    // 按过期时间从早到晚检查最多count个带过期时间的键，删除其中已过期的
+    // 返回 (检查数, 删除数)
+    pub async fn expire_sample(&mut self, db: u64, count: usize) -> (usize, usize) {
+        let now = self.now_millis();
+        let (sampled, expired) = match self.expires.get(&db) {
+            Some(index) => {
+                let earliest = index.earliest(count);
+                let expired: Vec<Bytes> = earliest
+                    .iter()
+                    .filter(|(deadline, _)| *deadline < now)
+                    .map(|(_, k)| k.clone())
+                    .collect();
+                (earliest.len(), expired)
+            }
+            None => return (0, 0),
+        };
+        for k in expired.iter() {
+            self.delete_expired(db, k).await;
+        }
+        (sampled, expired.len())
+    }
+
+    // 键的过期时间(ms)，不需要查找键值对本身
+    pub async fn get_expiry(&self, db: u64, key: &[u8]) -> Option<u64> {
+        self.expires.get(&db)?.get(key)
+    }
+
+    // 设置或清除键的过期时间，键不存在时返回false
+    pub async fn set_expiry(&mut self, db: u64, key: &[u8], expiry: Option<Expiry>) -> bool {
+        let Some(database) = self.databases.get(&db) else {
+            return false;
+        };
+        let Some(mut kv) = database.get_mut(key) else {
+            return false;
+        };
+        kv.expiry = expiry;
+        self.dirty += 1;
+        self.changes += 1;
+        let mut index = self.expires.entry(db).or_default();
+        match expiry {
+            Some(e) => index.set(kv.key().clone(), e.as_millis()),
+            None => {
+                index.remove(key);
+            }
+        }
+        true
+    }
+
+    // 交换两个数据库的全部键值对和过期索引(SWAPDB)
+    pub fn swap_db(&mut self, a: u64, b: u64) {
+        swap_entries(&self.databases, a, b);
+        swap_entries(&self.expires, a, b);
+        self.dirty += 1;
+        self.changes += 1;
+    }
+
+    // 带过期时间的键的数量
+    pub fn expires_size(&self, db: u64) -> usize {
+        self.expires.get(&db).map(|e| e.len()).unwrap_or(0)
+    }
+
+    // 带过期时间的键的平均剩余时间(ms)
+    pub fn avg_ttl(&self, db: u64) -> u64 {
+        let Some(index) = self.expires.get(&db) else {
+            return 0;
+        };
+        if index.len() == 0 {
+            return 0;
+        }
+        let now = self.now_millis();
+        let total: u64 = index
+            .by_key
+            .values()
+            .map(|deadline| deadline.saturating_sub(now))
+            .sum();
+        total / index.len() as u64
+    }
+
     // 异步获取所有键
-    pub async fn keys(&self, db: u64) -> Option<Vec<String>> {
+    pub async fn keys(&self, db: u64) -> Option<Vec<Bytes>> {
         if let Some(database) = self.databases.get(&db) {
             Some(database.iter().map(|entry| entry.key().clone()).collect())
         } else {
@@ -225,15 +519,50 @@ impl RdbFile {
     }
 }
 
+fn swap_entries<V>(map: &DashMap<u64, V>, a: u64, b: u64) {
+    let va = map.remove(&a).map(|(_, v)| v);
+    let vb = map.remove(&b).map(|(_, v)| v);
+    if let Some(v) = va {
+        map.insert(b, v);
+    }
+    if let Some(v) = vb {
+        map.insert(a, v);
+    }
+}
+
+use crate::crc64::Digest;
+use crate::encodings::{decode_intset, decode_listpack, decode_ziplist, decode_zipmap};
+use crate::lzf;
+use crate::resp::DEFAULT_PROTO_MAX_BULK_LEN;
 use anyhow::{bail, Context, Result};
 use byteorder::{BigEndian, LittleEndian};
-use crc64fast::Digest;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
 
+// 从该版本开始RDB文件末尾带有CRC64校验和
+const RDB_CHECKSUM_VERSION: u32 = 5;
+
 // RDB文件异步解析器
 pub struct RdbParser<R: AsyncReadExt + AsyncSeekExt + Unpin> {
     reader: R,
     crc: Digest,
+    skip_checksum: bool,
+    // 字符串的最大长度(proto-max-bulk-len)
+    max_bulk_len: u64,
+    // 正在解析的条目(辅助字段、数据库选择器或键值对)的起始偏移，解析出错时用于定位
+    entry_offset: u64,
+    checksum: ChecksumStatus,
+}
+
+// RDB文件末尾校验和的检查结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChecksumStatus {
+    // 版本5之前的文件没有校验和
+    Missing,
+    // 保存时关闭了校验，存储的值为0
+    Disabled,
+    Ok(u64),
+    // 只有设置了skip_checksum才会在不匹配时继续加载
+    Mismatch { stored: u64, computed: u64 },
 }
 
 impl<R: AsyncReadExt + AsyncSeekExt + Unpin> RdbParser<R> {
@@ -241,9 +570,40 @@ impl<R: AsyncReadExt + AsyncSeekExt + Unpin> RdbParser<R> {
         Self {
             reader,
             crc: Digest::new(),
+            skip_checksum: false,
+            max_bulk_len: DEFAULT_PROTO_MAX_BULK_LEN,
+            entry_offset: 0,
+            checksum: ChecksumStatus::Missing,
         }
     }
 
+    // 不校验CRC64(用于从损坏的文件中恢复数据)
+    pub fn skip_checksum(mut self, skip: bool) -> Self {
+        self.skip_checksum = skip;
+        self
+    }
+
+    // 超过该长度的字符串视为文件损坏
+    pub fn max_bulk_len(mut self, max: u64) -> Self {
+        self.max_bulk_len = max;
+        self
+    }
+
+    // 最后一个开始解析的条目的偏移，parse出错时即出错条目的位置
+    pub fn entry_offset(&self) -> u64 {
+        self.entry_offset
+    }
+
+    // parse成功后校验和的检查结果
+    pub fn checksum(&self) -> ChecksumStatus {
+        self.checksum
+    }
+
+    // 取回读取器，RDB之后还有其他数据时(如AOF的RDB前导)从这里继续读
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
     // 异步解析整个RDB文件
     pub async fn parse(&mut self) -> Result<RdbFile> {
         // 读取并验证魔数
@@ -266,6 +626,7 @@ impl<R: AsyncReadExt + AsyncSeekExt + Unpin> RdbParser<R> {
         let mut current_db = DB_NUM;
 
         'outer: loop {
+            self.entry_offset = self.reader.seek(SeekFrom::Current(0)).await?;
             let byte = self.read_u8().await?;
             match byte {
                 TYPE_AUX => {
@@ -278,7 +639,7 @@ impl<R: AsyncReadExt + AsyncSeekExt + Unpin> RdbParser<R> {
                 }
                 TYPE_SELECTDB => {
                     // 数据库选择器
-                    current_db = self.read_u8().await? as u64;
+                    current_db = self.read_length().await?;
                     rdb_file
                         .databases
                         .entry(current_db)
@@ -289,69 +650,57 @@ impl<R: AsyncReadExt + AsyncSeekExt + Unpin> RdbParser<R> {
                         self.read_u8().await?; // 消耗掉0xFB
                         let hashmap_size = self.read_length().await?;
                         _expires_size = self.read_length().await?;
-                        rdb_file.set_capacity(hashmap_size as usize)?;
+                        rdb_file.set_capacity(current_db, hashmap_size);
                     }
                     // 解析该数据库中的所有键值对
-                    #[allow(unused_assignments)]
-                    let mut key: String = "".to_string();
-                    #[allow(unused_assignments)]
-                    let mut key_value: KeyValue = KeyValue {
-                        value: RedisValue::String("".to_string()),
-                        expiry: None,
-                    };
-
                     loop {
-                        // 解析键值对
+                        self.entry_offset = self.reader.seek(SeekFrom::Current(0)).await?;
                         let byte = self.peek_u8().await?;
-                        match byte {
+                        let expiry = match byte {
                             TYPE_EXPIRETIME | TYPE_EXPIRETIME_MS => {
                                 // 处理带过期时间的键值对,读取过期时间
-                                let expiry = match self.read_u8().await? {
-                                    TYPE_EXPIRETIME => {
-                                        Expiry::Seconds(self.read_u32::<LittleEndian>().await?)
-                                    }
-                                    TYPE_EXPIRETIME_MS => {
-                                        Expiry::Milliseconds(self.read_u64::<LittleEndian>().await?)
+                                Some(match self.read_u8().await? {
+                                    TYPE_EXPIRETIME => Expiry::from_secs(
+                                        self.read_u32::<LittleEndian>().await? as u64,
+                                    ),
+                                    _ => {
+                                        Expiry::from_millis(self.read_u64::<LittleEndian>().await?)
                                     }
-                                    _ => bail!("unknown expire time type!"),
-                                };
-                                let value_type = self.read_u8().await?;
-                                let (key_1, value) = self.parse_value(value_type).await?;
-                                key = key_1;
-
-                                key_value = KeyValue {
-                                    value,
-                                    expiry: Some(expiry),
-                                };
+                                })
                             }
                             TYPE_SELECTDB => {
                                 break;
                             }
                             TYPE_EOF => {
+                                self.read_u8().await?;
                                 break 'outer;
                             }
-                            // 没有过期时间的键值对
-                            _ => {
-                                let value_type = self.read_u8().await?;
-                                let (key_1, value) = self.parse_value(value_type).await?;
-                                key = key_1;
-                                log::debug!("{key}:{:?}", value);
-
-                                key_value = KeyValue {
-                                    value: value,
-                                    expiry: None,
-                                };
+                            // 模块辅助数据(when=AFTER_RDB)写在所有键之后
+                            TYPE_FUNCTION2 | TYPE_MODULE_AUX => {
+                                self.read_u8().await?;
+                                self.skip_opcode(byte).await?;
+                                continue;
                             }
-                        }
-
+                            // 没有过期时间的键值对
+                            _ => None,
+                        };
+                        let value_type = self.read_value_type().await?;
+                        let (key, value) = self.parse_value(value_type).await?;
+                        log::debug!("{:?}:{:?}", key, value);
+                        // 不支持的类型已跳过
+                        let Some(value) = value else {
+                            continue;
+                        };
+
+                        // 后出现的同名键覆盖之前的值
                         rdb_file
-                            .databases
-                            .entry(current_db)
-                            .or_insert(DashMap::new())
-                            .entry(key)
-                            .or_insert(key_value);
+                            .insert_or_replace(current_db, key, KeyValue { value, expiry })
+                            .await;
                     }
                 }
+                TYPE_FUNCTION2 | TYPE_MODULE_AUX => {
+                    self.skip_opcode(byte).await?;
+                }
                 TYPE_EOF => {
                     break;
                 }
@@ -363,28 +712,49 @@ impl<R: AsyncReadExt + AsyncSeekExt + Unpin> RdbParser<R> {
                 }
             }
         }
-        // 验证CRC64校验和
-        // let file_size = self.reader.seek(SeekFrom::End(0)).await?;
-        // self.reader.seek(SeekFrom::Start(file_size - 8)).await?;
-        self.pass_u8().await?;
-        match self.peek_u8().await {
-            Ok(_) => {
-                let stored_checksum = self.read_u64::<BigEndian>().await?;
-                let computed_checksum = self.crc.sum64();
-
-                log::debug!(
-                    "stored :{:02x} computed :{:02x}",
+        // 验证CRC64校验和：覆盖EOF(0xFF)及之前的所有字节，以小端存储，0表示未启用校验
+        if version >= RDB_CHECKSUM_VERSION {
+            let computed_checksum = self.crc.sum64();
+            let mut buf = [0u8; 8];
+            self.reader
+                .read_exact(&mut buf)
+                .await
+                .context("Unexpected EOF reading RDB checksum")?;
+            let stored_checksum = u64::from_le_bytes(buf);
+
+            log::debug!(
+                "stored :{:016x} computed :{:016x}",
+                stored_checksum,
+                computed_checksum
+            );
+
+            if stored_checksum == 0 {
+                log::debug!("RDB file was saved with checksum disabled: no check performed.");
+                self.checksum = ChecksumStatus::Disabled;
+            } else if stored_checksum == computed_checksum {
+                self.checksum = ChecksumStatus::Ok(stored_checksum);
+            } else {
+                if !self.skip_checksum {
+                    anyhow::bail!(
+                        "Wrong RDB checksum expected: ({:016x}) got: ({:016x}). Aborting now.",
+                        stored_checksum,
+                        computed_checksum
+                    );
+                }
+                log::warn!(
+                    "Wrong RDB checksum expected: ({:016x}) got: ({:016x}), loading anyway",
                     stored_checksum,
                     computed_checksum
                 );
-
-                // if stored_checksum != computed_checksum {
-                //     anyhow::bail!("CRC64 checksum mismatch");
-                // }
+                self.checksum = ChecksumStatus::Mismatch {
+                    stored: stored_checksum,
+                    computed: computed_checksum,
+                };
             }
-            Err(e) => return Err(e),
         }
 
+        // 加载的数据不算作未保存的修改
+        rdb_file.clear_dirty(rdb_file.dirty());
         Ok(rdb_file)
     }
 
@@ -393,116 +763,345 @@ impl<R: AsyncReadExt + AsyncSeekExt + Unpin> RdbParser<R> {
         // 读取键和值（均为Redis字符串类型）
 
         loop {
-            let key = self.read_string().await?;
+            let key = self.read_text().await?;
 
             // 处理已知字段
             match key.as_str() {
                 "redis-ver" => {
-                    let value = self.read_string().await?;
+                    let value = self.read_text().await?;
                     rdb_file.aux_fields.insert(key.clone(), value.clone());
                 }
                 "redis-bits" => {
-                    let value = self.read_length().await?;
+                    // 以整数编码保存
+                    let value = self.read_text().await?;
                     log::debug!("redis bits is {value}");
-                    if value == 32 {
-                        rdb_file.aux_fields.insert(key.clone(), "32".to_string());
-                    } else if value == 64 {
-                        rdb_file.aux_fields.insert(key.clone(), "64".to_string());
-                    }
+                    rdb_file.aux_fields.insert(key.clone(), value);
                 }
                 "ctime" => {
-                    let value = self.read_string().await?;
+                    let value = self.read_text().await?;
                     rdb_file.aux_fields.insert(key.clone(), value.clone());
                 }
                 "used-mem" => {
-                    let value = self.read_string().await?;
+                    let value = self.read_text().await?;
                     rdb_file.aux_fields.insert(key.clone(), value.clone());
                 }
                 _ => {
-                    // 忽略未知字段
-                    log::debug!("Ignoring unknown auxiliary field: {}", key);
+                    // 其他字段(如repl-id、aof-base)只记录下来，加载时不使用
+                    let value = self.read_text().await?;
+                    log::debug!("Unknown auxiliary field: {} {}", key, value);
+                    rdb_file.aux_fields.insert(key.clone(), value);
                 }
             }
-            if self
-                .peek_u8()
-                .await
-                .is_ok_and(|b| b == TYPE_AUX || b == TYPE_SELECTDB)
-            {
+            if self.peek_u8().await.is_ok_and(|b| {
+                matches!(
+                    b,
+                    TYPE_AUX | TYPE_SELECTDB | TYPE_EOF | TYPE_FUNCTION2 | TYPE_MODULE_AUX
+                )
+            }) {
                 break;
             }
         }
         Ok(())
     }
 
-    // 解析不同类型的值
-    async fn parse_value(&mut self, value_type: u8) -> Result<(String, RedisValue)> {
-        match value_type {
-            0x00 => {
-                // 简单字符串
-                let k = self.read_string().await?;
-                let v = self.read_string().await?;
-                Ok((k, RedisValue::String(v)))
-            }/*
-            0x01 => {
-                // 列表
-                let k = self.read_string().await?;
-                let len = self.read_length().await?;
-                let mut list = Vec::with_capacity(len as usize);
-                for _ in 0..len {
-                    list.push(self.read_string().await?);
+    // 跳过函数库(FUNCTION2)或模块辅助数据(MODULE_AUX)，操作码已读取。
+    // 不支持函数和模块，只给出警告，其余数据照常加载
+    async fn skip_opcode(&mut self, opcode: u8) -> Result<()> {
+        if opcode == TYPE_FUNCTION2 {
+            // 库的源代码
+            self.read_blob().await?;
+            log::warn!("Skipping a function library: functions are not supported");
+            return Ok(());
+        }
+        let module_id = self.read_length().await?;
+        // when的操作码(总是UINT)和when
+        self.read_length().await?;
+        self.read_length().await?;
+        self.skip_module_value().await?;
+        log::warn!(
+            "Skipping aux data of module '{}': modules are not supported",
+            module_type_name(module_id)
+        );
+        Ok(())
+    }
+
+    // 跳过模块保存的值：一串带操作码的数值和字符串，以EOF操作码结束
+    async fn skip_module_value(&mut self) -> Result<()> {
+        loop {
+            match self.read_length().await? {
+                RDB_MODULE_OPCODE_EOF => return Ok(()),
+                RDB_MODULE_OPCODE_SINT | RDB_MODULE_OPCODE_UINT => {
+                    self.read_length().await?;
                 }
-                Ok((k, RedisValue::List(list)))
+                RDB_MODULE_OPCODE_FLOAT => {
+                    self.read_bytes(&mut [0u8; 4]).await?;
+                }
+                RDB_MODULE_OPCODE_DOUBLE => {
+                    self.read_bytes(&mut [0u8; 8]).await?;
+                }
+                RDB_MODULE_OPCODE_STRING => {
+                    self.read_blob().await?;
+                }
+                opcode => bail!("Unknown module value opcode {}", opcode),
             }
-            0x02 => {
-                // 哈希
-                let len = self.read_length().await?;
-                let mut hash = Vec::with_capacity(len as usize);
-                for _ in 0..len {
-                    let key = self.read_string().await?;
-                    let value = self.read_string().await?;
-                    hash.push((key, value));
+        }
+    }
+
+    // 跳过一个stream：listpack节点、元数据、消费者组及其待处理列表
+    async fn skip_stream(&mut self, value_type: u8) -> Result<()> {
+        let listpacks = self.read_length().await?;
+        for _ in 0..listpacks {
+            // 节点的主ID和listpack
+            self.read_blob().await?;
+            self.read_blob().await?;
+        }
+        // 元素个数、最后的ID；版本2起还有第一个ID、最大删除ID和添加过的元素数
+        let fields = if value_type == RDB_TYPE_STREAM_LISTPACKS {
+            3
+        } else {
+            8
+        };
+        for _ in 0..fields {
+            self.read_length().await?;
+        }
+        let groups = self.read_length().await?;
+        for _ in 0..groups {
+            // 组名、最后投递的ID，版本2起还有已读取的元素数
+            self.read_blob().await?;
+            self.read_length().await?;
+            self.read_length().await?;
+            if value_type != RDB_TYPE_STREAM_LISTPACKS {
+                self.read_length().await?;
+            }
+            // 组的待处理列表：16字节ID、投递时间(ms)、投递次数
+            let pending = self.read_length().await?;
+            for _ in 0..pending {
+                self.read_bytes(&mut [0u8; 16]).await?;
+                self.read_u64::<LittleEndian>().await?;
+                self.read_length().await?;
+            }
+            let consumers = self.read_length().await?;
+            for _ in 0..consumers {
+                // 消费者名、最后出现时间，版本3起还有最后活跃时间
+                self.read_blob().await?;
+                self.read_u64::<LittleEndian>().await?;
+                if value_type == RDB_TYPE_STREAM_LISTPACKS_3 {
+                    self.read_u64::<LittleEndian>().await?;
+                }
+                // 消费者的待处理列表只有ID
+                let pending = self.read_length().await?;
+                for _ in 0..pending {
+                    self.read_bytes(&mut [0u8; 16]).await?;
                 }
-                Ok(RedisValue::Hash(hash))
             }
-            0x03 => {
-                // 集合
-                let len = self.read_length().await?;
-                let mut set = Vec::with_capacity(len as usize);
-                for _ in 0..len {
-                    set.push(self.read_string().await?);
+        }
+        Ok(())
+    }
+
+    // 读取值类型，跳过其前面的LRU空闲时间(IDLE)和LFU计数(FREQ)，加载时不使用
+    async fn read_value_type(&mut self) -> Result<u8> {
+        loop {
+            match self.read_u8().await? {
+                TYPE_IDLE => {
+                    self.read_length().await?;
+                }
+                TYPE_FREQ => {
+                    self.read_u8().await?;
                 }
-                Ok(RedisValue::Set(set))
+                value_type => return Ok(value_type),
             }
-            0x04 => {
-                // 有序集合
+        }
+    }
+
+    // 解析不同类型的值，返回键和值；不支持的类型(stream)被跳过，值为None
+    async fn parse_value(&mut self, value_type: u8) -> Result<(Bytes, Option<RedisValue>)> {
+        let k = self.read_string().await?;
+        let v = match value_type {
+            RDB_TYPE_STRING => RedisValue::String(self.read_string().await?),
+            RDB_TYPE_LIST => RedisValue::List(self.read_string_list().await?),
+            RDB_TYPE_SET => RedisValue::Set(self.read_string_list().await?),
+            RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+                // ZSET的分数为字符串编码，ZSET_2为8字节二进制
                 let len = self.read_length().await?;
-                let mut sorted_set = Vec::with_capacity(len as usize);
+                let mut sorted_set = Vec::with_capacity(len.min(1024) as usize);
                 for _ in 0..len {
                     let element = self.read_string().await?;
-                    let score = self.read_double().await?;
+                    let score = if value_type == RDB_TYPE_ZSET {
+                        self.read_double().await?
+                    } else {
+                        self.read_f64::<LittleEndian>().await?
+                    };
+                    sorted_set.push((element, score));
+                }
+                // Redis从尾部开始保存，加载后按分数重新排序
+                sorted_set.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+                RedisValue::SortedSet(sorted_set)
+            }
+            RDB_TYPE_SET_INTSET => {
+                RedisValue::Set(into_bytes(decode_intset(&self.read_blob().await?)?))
+            }
+            RDB_TYPE_LIST_ZIPLIST => {
+                RedisValue::List(into_bytes(decode_ziplist(&self.read_blob().await?)?))
+            }
+            RDB_TYPE_SET_LISTPACK => {
+                RedisValue::Set(into_bytes(decode_listpack(&self.read_blob().await?)?))
+            }
+            RDB_TYPE_LIST_QUICKLIST | RDB_TYPE_LIST_QUICKLIST_2 => {
+                RedisValue::List(self.read_quicklist(value_type).await?)
+            }
+            RDB_TYPE_HASH_ZIPMAP | RDB_TYPE_HASH_ZIPLIST | RDB_TYPE_HASH_LISTPACK => {
+                let blob = self.read_blob().await?;
+                let entries = match value_type {
+                    RDB_TYPE_HASH_ZIPMAP => decode_zipmap(&blob)?,
+                    RDB_TYPE_HASH_ZIPLIST => decode_ziplist(&blob)?,
+                    _ => decode_listpack(&blob)?,
+                };
+                RedisValue::Hash(into_pairs(into_bytes(entries))?)
+            }
+            RDB_TYPE_ZSET_ZIPLIST | RDB_TYPE_ZSET_LISTPACK => {
+                let blob = self.read_blob().await?;
+                let entries = if value_type == RDB_TYPE_ZSET_ZIPLIST {
+                    decode_ziplist(&blob)?
+                } else {
+                    decode_listpack(&blob)?
+                };
+                // 紧凑编码中分数以字符串或整数保存，元素已按分数排序
+                let mut sorted_set = Vec::with_capacity(entries.len() / 2);
+                for (element, score) in into_pairs(into_bytes(entries))? {
+                    let score = std::str::from_utf8(&score)
+                        .ok()
+                        .and_then(|s| s.parse::<f64>().ok())
+                        .with_context(|| {
+                            format!(
+                                "Invalid sorted set score: {}",
+                                String::from_utf8_lossy(&score)
+                            )
+                        })?;
                     sorted_set.push((element, score));
                 }
-                Ok(RedisValue::SortedSet(sorted_set))
-            }*/
+                RedisValue::SortedSet(sorted_set)
+            }
+            RDB_TYPE_STREAM_LISTPACKS
+            | RDB_TYPE_STREAM_LISTPACKS_2
+            | RDB_TYPE_STREAM_LISTPACKS_3 => {
+                self.skip_stream(value_type).await?;
+                log::warn!(
+                    "Skipping key '{}': stream values are not supported",
+                    String::from_utf8_lossy(&k)
+                );
+                return Ok((k, None));
+            }
+            RDB_TYPE_HASH => {
+                let len = self.read_length().await?;
+                let mut hash = Vec::with_capacity(len.min(1024) as usize);
+                for _ in 0..len {
+                    let field = self.read_string().await?;
+                    let value = self.read_string().await?;
+                    hash.push((field, value));
+                }
+                RedisValue::Hash(hash)
+            }
             // 其他类型的解析实现...
             _ => anyhow::bail!("Unsupported value type: {}", value_type),
+        };
+        Ok((k, Some(v)))
+    }
+
+    // 读取长度加字符串列表(列表和集合)
+    async fn read_string_list(&mut self) -> Result<Vec<Bytes>> {
+        let len = self.read_length().await?;
+        let mut list = Vec::with_capacity(len.min(1024) as usize);
+        for _ in 0..len {
+            list.push(self.read_string().await?);
         }
+        Ok(list)
+    }
+
+    // 读取字符串(二进制安全)
+    async fn read_string(&mut self) -> Result<Bytes> {
+        Ok(Bytes::from(self.read_blob().await?))
     }
 
-    // 读取字符串
-    async fn read_string(&mut self) -> Result<String> {
+    // 读取文本字符串(辅助字段等元数据)
+    async fn read_text(&mut self) -> Result<String> {
+        let bytes = self.read_blob().await?;
+        String::from_utf8(bytes).context("Failed to convert bytes to String")
+    }
+
+    // 读取字符串编码的原始字节(ziplist等紧凑编码也以这种方式保存)
+    // 字符串可能是普通长度+内容，也可能是以0xC0-0xC3开头的整数编码或LZF压缩
+    async fn read_blob(&mut self) -> Result<Vec<u8>> {
+        let first_byte = self.peek_u8().await?;
+        if first_byte & 0xC0 == 0xC0 {
+            self.read_u8().await?;
+            return match first_byte & 0x3F {
+                // 8/16/32位有符号整数，转换为十进制文本
+                RDB_ENC_INT8 => Ok(self.read_i8().await?.to_string().into_bytes()),
+                RDB_ENC_INT16 => Ok(self
+                    .read_i16::<LittleEndian>()
+                    .await?
+                    .to_string()
+                    .into_bytes()),
+                RDB_ENC_INT32 => Ok(self
+                    .read_i32::<LittleEndian>()
+                    .await?
+                    .to_string()
+                    .into_bytes()),
+                RDB_ENC_LZF => {
+                    // 压缩后长度、原始长度、压缩数据
+                    let clen = self.read_length().await?;
+                    let len = self.read_length().await?;
+                    self.check_bulk_len(len)?;
+                    self.check_bulk_len(clen)?;
+                    let mut compressed = vec![0u8; clen as usize];
+                    self.read_bytes(&mut compressed).await?;
+                    lzf::decompress(&compressed, len as usize)
+                }
+                code => anyhow::bail!("Unknown RDB string encoding type {}", code),
+            };
+        }
+
         let len = self.read_length().await?;
         log::debug!("read length is {len}");
 
-        // 添加最大长度限制，防止内存溢出
-        if len > 1024 * 1024 {
-            // 限制最大1MB
-            anyhow::bail!("String length exceeds maximum allowed size: {}", len);
-        }
+        self.check_bulk_len(len)?;
 
         let mut bytes = vec![0u8; len as usize];
         self.read_bytes(&mut bytes).await?;
-        String::from_utf8(bytes).context("Failed to convert bytes to String")
+        Ok(bytes)
+    }
+
+    // 长度限制，防止损坏的长度字段导致分配过大的内存
+    fn check_bulk_len(&self, len: u64) -> Result<()> {
+        if len > self.max_bulk_len {
+            anyhow::bail!(
+                "String length {} exceeds proto-max-bulk-len {}",
+                len,
+                self.max_bulk_len
+            );
+        }
+        Ok(())
+    }
+
+    // quicklist: 节点数量后跟每个节点的ziplist
+    // quicklist2: 每个节点先有容器类型，PLAIN节点是单个元素，PACKED节点是listpack
+    async fn read_quicklist(&mut self, value_type: u8) -> Result<Vec<Bytes>> {
+        let nodes = self.read_length().await?;
+        let mut list = Vec::new();
+        for _ in 0..nodes {
+            if value_type == RDB_TYPE_LIST_QUICKLIST {
+                list.extend(into_bytes(decode_ziplist(&self.read_blob().await?)?));
+                continue;
+            }
+            match self.read_length().await? {
+                QUICKLIST_NODE_PLAIN => list.push(self.read_string().await?),
+                QUICKLIST_NODE_PACKED => {
+                    list.extend(into_bytes(decode_listpack(&self.read_blob().await?)?))
+                }
+                c => anyhow::bail!("Unknown quicklist node container: {}", c),
+            }
+        }
+        Ok(list)
     }
 
     // 读取长度编码
@@ -522,68 +1121,46 @@ impl<R: AsyncReadExt + AsyncSeekExt + Unpin> RdbParser<R> {
                 let second_byte = self.read_u8().await?;
                 Ok((((first_byte & 0x3F) as u64) << 8) | (second_byte as u64))
             }
-            2 => {
-                // 10: 丢弃剩余6位，接下来4字节表示长度
-                let len = self.read_u32::<LittleEndian>().await?;
-                Ok(len as u64)
-            }
+            2 => match first_byte {
+                // 0x80: 接下来4字节(大端)表示长度
+                0x80 => Ok(self.read_u32::<BigEndian>().await? as u64),
+                // 0x81: 接下来8字节(大端)表示长度
+                0x81 => self.read_u64::<BigEndian>().await,
+                _ => anyhow::bail!("Unsupported length encoding: {:02X}", first_byte),
+            },
             3 => {
-                // 11: 特殊格式编码
-                let special_code = first_byte & 0x3F;
-
-                match special_code {
-                    0 => {
-                        // 8位有符号整数
-                        let value = self.read_i8().await?;
-                        Ok(value as u64)
-                    }
-                    1 => {
-                        // 16位有符号整数
-                        let value = self.read_i16::<LittleEndian>().await?;
-                        Ok(value as u64)
-                    }
-                    2 => {
-                        // 32位有符号整数
-                        let value = self.read_i32::<LittleEndian>().await?;
-                        Ok(value as u64)
-                    }
-                    3 => {
-                        // LZF压缩字符串（此处返回压缩标记，实际解析在别处处理）
-                        Err(anyhow::anyhow!("LZF compressed string length encoding should be handled in string parsing context"))
-                    }
-                    _ => {
-                        anyhow::bail!("Unsupported special length encoding: {}", special_code)
-                    }
-                }
+                // 11: 特殊格式编码只用于字符串，由read_blob处理
+                anyhow::bail!(
+                    "Unexpected string encoding {:02X} where a length was expected",
+                    first_byte
+                )
             }
             _ => unreachable!(), // 前两位只能是00,01,10,11
         }
     }
 
-    // 读取双精度浮点数
+    // 读取字符串编码的双精度浮点数(ZSET的分数)
+    // 1字节长度后跟十进制文本，长度253/254/255分别表示nan/+inf/-inf
     async fn read_double(&mut self) -> Result<f64> {
-        let len = self.read_length().await?;
-
-        if len == 253 {
-            // 特殊值: +inf
-            Ok(f64::INFINITY)
-        } else if len == 254 {
-            // 特殊值: -inf
-            Ok(f64::NEG_INFINITY)
-        } else if len == 255 {
-            // 特殊值: nan
-            Ok(f64::NAN)
-        } else if len == 8 {
-            // 正常的64位双精度浮点数
-            Ok(self.read_f64::<LittleEndian>().await?)
-        } else {
-            anyhow::bail!("Unsupported double length: {}", len)
+        let len = self.read_u8().await?;
+        match len {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            _ => {
+                let mut buf = vec![0u8; len as usize];
+                self.read_bytes(&mut buf).await?;
+                std::str::from_utf8(&buf)
+                    .ok()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .with_context(|| format!("Invalid double value: {:?}", buf))
+            }
         }
     }
 
     // 辅助读取方法，同时更新CRC
     async fn read_bytes(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let bytes_read = self.reader.read(buf).await?;
+        let bytes_read = self.reader.read_exact(buf).await?;
         log::debug!("bytes read is {:02x?}", &buf[..bytes_read]);
         self.crc.write(&buf[0..bytes_read]);
         Ok(bytes_read)
@@ -654,70 +1231,101 @@ impl<R: AsyncReadExt + AsyncSeekExt + Unpin> RdbParser<R> {
         self.reader.seek(SeekFrom::Start(current_pos)).await?;
         Ok(byte)
     }
-    // 读取一个u8,但不加入crc校验
-    async fn pass_u8(&mut self) -> Result<u8> {
-        let byte = self.reader.read_u8().await?;
-        Ok(byte)
+}
+// 模块类型ID的高54位是9个字符的名字，每个字符6位
+fn module_type_name(module_id: u64) -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    (0..9)
+        .rev()
+        .map(|i| CHARSET[((module_id >> (10 + 6 * i)) & 63) as usize] as char)
+        .collect()
+}
+
+// 紧凑编码解出的元素
+fn into_bytes(entries: Vec<Vec<u8>>) -> Vec<Bytes> {
+    entries.into_iter().map(Bytes::from).collect()
+}
+
+// 交替排列的键和值(哈希字段/值，有序集合元素/分数)组成键值对
+fn into_pairs(entries: Vec<Bytes>) -> Result<Vec<(Bytes, Bytes)>> {
+    if entries.len() % 2 != 0 {
+        anyhow::bail!("Odd number of entries in a key/value encoding");
+    }
+    let mut pairs = Vec::with_capacity(entries.len() / 2);
+    let mut it = entries.into_iter();
+    while let (Some(k), Some(v)) = (it.next(), it.next()) {
+        pairs.push((k, v));
     }
+    Ok(pairs)
 }
-/*
+
 // RDB文件异步写入器
-pub struct RdbWriter<W: AsyncWriteExt + AsyncSeekExt + Unpin> {
+pub struct RdbWriter<W: AsyncWriteExt + Unpin> {
     writer: W,
     crc: Digest,
+    compression: bool,
 }
 
-impl<W: AsyncWriteExt + AsyncSeekExt + Unpin> RdbWriter<W> {
+impl<W: AsyncWriteExt + Unpin> RdbWriter<W> {
     pub fn new(writer: W) -> Self {
         Self {
             writer,
             crc: Digest::new(),
+            compression: true,
         }
     }
 
+    // 是否用LZF压缩较长的字符串(rdbcompression)
+    pub fn compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
     // 异步写入整个RDB文件
     pub async fn write(&mut self, rdb_file: &RdbFile) -> Result<()> {
-        // 写入魔数和版本号
-        self.write_bytes(b"REDIS").await?;
-        self.write_bytes(format!("{:04}", rdb_file.version).as_bytes())
+        // 写入魔数和版本号：内容总是按RDB_VERSION的格式写(辅助字段、ZSET_2等)，
+        // 不能沿用加载时的文件版本
+        self.write_bytes(MAGIC_STRING).await?;
+        self.write_bytes(format!("{:04}", RDB_VERSION).as_bytes())
             .await?;
 
         // 写入辅助字段
-        for e in rdb_file.aux_fields.iter() {
-            self.write_u8(0xFA).await?;
-            self.write_string(e.key()).await?;
-            self.write_string(e.value()).await?;
-        }
+        self.write_u8(TYPE_AUX).await?;
+        self.write_string(b"redis-ver").await?;
+        self.write_string(REDIS_VER.as_bytes()).await?;
+        self.write_u8(TYPE_AUX).await?;
+        self.write_string(b"redis-bits").await?;
+        self.write_string(usize::BITS.to_string().as_bytes())
+            .await?;
 
-        // 写入各个数据库
-        for e in rdb_file.databases.iter() {
-            // 写入数据库选择器
-            let db_num = e.key();
-            let db_map = e.value();
+        // 写入各个数据库，跳过空数据库
+        let mut dbs: Vec<u64> = rdb_file.databases.iter().map(|e| *e.key()).collect();
+        dbs.sort();
+        for db_num in dbs {
+            let Some(db_map) = rdb_file.databases.get(&db_num) else {
+                continue;
+            };
+            if db_map.is_empty() {
+                continue;
+            }
 
-            self.write_u8(0xFE).await?;
-            self.write_length(*db_num as u64).await?;
+            // 写入数据库选择器
+            self.write_u8(TYPE_SELECTDB).await?;
+            self.write_length(db_num).await?;
 
-            // 写入RESIZEDB字段 (简化处理，使用估算值)
-            self.write_u8(0xFB).await?;
+            // 写入RESIZEDB字段
+            self.write_u8(TYPE_RESIZEDB).await?;
             self.write_length(db_map.len() as u64).await?; // 哈希表大小
-            self.write_length(0).await?; // 过期哈希表大小
+            self.write_length(rdb_file.expires_size(db_num) as u64)
+                .await?; // 过期哈希表大小
 
             // 写入键值对
             for e in db_map.iter() {
                 let key = e.key();
                 let kv = e.value();
                 if let Some(expiry) = &kv.expiry {
-                    match expiry {
-                        Expiry::Seconds(secs) => {
-                            self.write_u8(0xFD).await?;
-                            self.write_u32::<LittleEndian>(*secs).await?;
-                        }
-                        Expiry::Milliseconds(millis) => {
-                            self.write_u8(0xFC).await?;
-                            self.write_u64::<LittleEndian>(*millis).await?;
-                        }
-                    }
+                    self.write_u8(TYPE_EXPIRETIME_MS).await?;
+                    self.write_u64::<LittleEndian>(expiry.as_millis()).await?;
                 }
 
                 // 写入值类型和键
@@ -730,12 +1338,12 @@ impl<W: AsyncWriteExt + AsyncSeekExt + Unpin> RdbWriter<W> {
         }
 
         // 写入文件结束标记
-        self.write_u8(0xFF).await?;
+        self.write_u8(TYPE_EOF).await?;
 
-        // 计算并写入CRC64校验和
+        // 写入CRC64校验和(小端)，校验和本身不参与计算
         let checksum = self.crc.sum64();
-        self.writer.seek(SeekFrom::End(0)).await?;
-        self.write_u64::<LittleEndian>(checksum).await?;
+        self.writer.write_all(&checksum.to_le_bytes()).await?;
+        self.writer.flush().await?;
 
         Ok(())
     }
@@ -743,11 +1351,11 @@ impl<W: AsyncWriteExt + AsyncSeekExt + Unpin> RdbWriter<W> {
     // 写入值类型
     async fn write_value_type(&mut self, value: &RedisValue) -> Result<()> {
         let type_byte = match value {
-            RedisValue::String(_) => 0x00,
-            RedisValue::List(_) => 0x01,
-            RedisValue::Hash(_) => 0x02,
-            RedisValue::Set(_) => 0x03,
-            RedisValue::SortedSet(_) => 0x04,
+            RedisValue::String(_) => RDB_TYPE_STRING,
+            RedisValue::List(_) => RDB_TYPE_LIST,
+            RedisValue::Set(_) => RDB_TYPE_SET,
+            RedisValue::SortedSet(_) => RDB_TYPE_ZSET_2,
+            RedisValue::Hash(_) => RDB_TYPE_HASH,
         };
         self.write_u8(type_byte).await
     }
@@ -756,7 +1364,7 @@ impl<W: AsyncWriteExt + AsyncSeekExt + Unpin> RdbWriter<W> {
     async fn write_value(&mut self, value: &RedisValue) -> Result<()> {
         match value {
             RedisValue::String(s) => self.write_string(s).await,
-            RedisValue::List(items) => {
+            RedisValue::List(items) | RedisValue::Set(items) => {
                 self.write_length(items.len() as u64).await?;
                 for item in items {
                     self.write_string(item).await?;
@@ -771,27 +1379,50 @@ impl<W: AsyncWriteExt + AsyncSeekExt + Unpin> RdbWriter<W> {
                 }
                 Ok(())
             }
-            RedisValue::Set(items) => {
-                self.write_length(items.len() as u64).await?;
-                for item in items {
-                    self.write_string(item).await?;
-                }
-                Ok(())
-            }
             RedisValue::SortedSet(items) => {
+                // ZSET_2: 分数为8字节二进制双精度浮点数
                 self.write_length(items.len() as u64).await?;
                 for (element, score) in items {
                     self.write_string(element).await?;
-                    self.write_double(*score).await?;
+                    self.write_f64::<LittleEndian>(*score).await?;
                 }
                 Ok(())
             }
         }
     }
 
-    // 写入字符串
-    async fn write_string(&mut self, s: &str) -> Result<()> {
-        let bytes = s.as_bytes();
+    // 写入字符串，与Redis一样优先使用整数编码，其次在开启压缩时尝试LZF
+    async fn write_string(&mut self, bytes: &[u8]) -> Result<()> {
+        // 只有能原样还原的十进制整数才使用整数编码("007"、"+1"等不行)
+        if bytes.len() <= 11 {
+            if let Some(n) = std::str::from_utf8(bytes)
+                .ok()
+                .and_then(|s| s.parse::<i32>().ok())
+                .filter(|n| n.to_string().as_bytes() == bytes)
+            {
+                return if let Ok(n) = i8::try_from(n) {
+                    self.write_u8(0xC0 | RDB_ENC_INT8).await?;
+                    self.write_bytes(&n.to_le_bytes()).await
+                } else if let Ok(n) = i16::try_from(n) {
+                    self.write_u8(0xC0 | RDB_ENC_INT16).await?;
+                    self.write_bytes(&n.to_le_bytes()).await
+                } else {
+                    self.write_u8(0xC0 | RDB_ENC_INT32).await?;
+                    self.write_bytes(&n.to_le_bytes()).await
+                };
+            }
+        }
+
+        // 较短的字符串压缩收益太小
+        if self.compression && bytes.len() > 20 {
+            if let Some(compressed) = lzf::compress(bytes) {
+                self.write_u8(0xC0 | RDB_ENC_LZF).await?;
+                self.write_length(compressed.len() as u64).await?;
+                self.write_length(bytes.len() as u64).await?;
+                return self.write_bytes(&compressed).await;
+            }
+        }
+
         self.write_length(bytes.len() as u64).await?;
         self.write_bytes(bytes).await
     }
@@ -805,43 +1436,27 @@ impl<W: AsyncWriteExt + AsyncSeekExt + Unpin> RdbWriter<W> {
             // 中等长度 (64-16383)
             self.write_u8(0x40 | ((len >> 8) as u8)).await?;
             self.write_u8((len & 0xFF) as u8).await
-        } else {
-            // 长长度
+        } else if len <= u32::MAX as u64 {
+            // 0x80: 接下来4字节(大端)表示长度
             self.write_u8(0x80).await?;
-            self.write_u64::<LittleEndian>(len).await
-        }
-    }
-
-    // 写入双精度浮点数
-    async fn write_double(&mut self, value: f64) -> Result<()> {
-        if value.is_infinite() && value.is_sign_positive() {
-            // +inf
-            self.write_length(253).await
-        } else if value.is_infinite() && value.is_sign_negative() {
-            // -inf
-            self.write_length(254).await
-        } else if value.is_nan() {
-            // nan
-            self.write_length(255).await
+            self.write_u32::<BigEndian>(len as u32).await
         } else {
-            // 正常的64位双精度浮点数
-            self.write_length(8).await?;
-            self.write_f64::<LittleEndian>(value).await
+            // 0x81: 接下来8字节(大端)表示长度
+            self.write_u8(0x81).await?;
+            self.write_u64::<BigEndian>(len).await
         }
     }
 
     // 辅助写入方法，同时更新CRC
     async fn write_bytes(&mut self, buf: &[u8]) -> Result<()> {
         self.writer.write_all(buf).await?;
-        let _ = self.crc.write(buf);
+        self.crc.write(buf);
         Ok(())
     }
 
     // 写入单个字节，同时更新CRC
     async fn write_u8(&mut self, byte: u8) -> Result<()> {
-        self.writer.write_all(&[byte]).await?;
-        let _ = self.crc.write(&[byte]);
-        Ok(())
+        self.write_bytes(&[byte]).await
     }
 
     // 写入整数，同时更新CRC
@@ -865,4 +1480,3 @@ impl<W: AsyncWriteExt + AsyncSeekExt + Unpin> RdbWriter<W> {
         self.write_bytes(&buf).await
     }
 }
-*/
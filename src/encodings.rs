@@ -0,0 +1,196 @@
+use anyhow::{bail, Result};
+
+// Decoders for the compact blobs Redis stores small collections in (zipmap, ziplist,
+// listpack, intset). Each RDB value of these types is a single string holding the blob;
+// the decoders turn it into its elements, integers rendered as decimal text like Redis does.
+
+// bounds-checked reader over a blob
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    what: &'static str,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buf: &'a [u8], what: &'static str) -> Self {
+        Cursor { buf, pos: 0, what }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.buf.len() - self.pos < n {
+            bail!("{} is truncated at offset {}", self.what, self.pos);
+        }
+        let s = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn peek(&self) -> Result<u8> {
+        match self.buf.get(self.pos) {
+            Some(b) => Ok(*b),
+            None => bail!("{} is truncated at offset {}", self.what, self.pos),
+        }
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.take(n).map(|_| ())
+    }
+
+    // little endian signed integer of `n` bytes, sign extended
+    fn int_le(&mut self, n: usize) -> Result<i64> {
+        let bytes = self.take(n)?;
+        let mut v: u64 = 0;
+        for (i, b) in bytes.iter().enumerate() {
+            v |= (*b as u64) << (8 * i);
+        }
+        let shift = 64 - 8 * n as u32;
+        Ok(((v << shift) as i64) >> shift)
+    }
+
+    fn u32_le(&mut self) -> Result<u32> {
+        Ok(self.int_le(4)? as u32)
+    }
+
+    fn u32_be(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+fn int_entry(v: i64) -> Vec<u8> {
+    v.to_string().into_bytes()
+}
+
+// ziplist: <zlbytes u32><zltail u32><zllen u16> <entry>... 0xFF
+// entry: <prevlen 1 or 5 bytes> <encoding> <data>
+pub fn decode_ziplist(blob: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut c = Cursor::new(blob, "ziplist");
+    c.skip(10)?;
+    let mut entries = Vec::new();
+    loop {
+        if c.peek()? == 0xFF {
+            break;
+        }
+        if c.u8()? == 0xFE {
+            c.skip(4)?;
+        }
+        let enc = c.u8()?;
+        let entry = match enc >> 6 {
+            0 => c.take((enc & 0x3F) as usize)?.to_vec(),
+            1 => {
+                let len = (((enc & 0x3F) as usize) << 8) | c.u8()? as usize;
+                c.take(len)?.to_vec()
+            }
+            2 => {
+                let len = c.u32_be()? as usize;
+                c.take(len)?.to_vec()
+            }
+            _ => match enc {
+                0xC0 => int_entry(c.int_le(2)?),
+                0xD0 => int_entry(c.int_le(4)?),
+                0xE0 => int_entry(c.int_le(8)?),
+                0xF0 => int_entry(c.int_le(3)?),
+                0xFE => int_entry(c.int_le(1)?),
+                // 1111xxxx: immediate 0..12 stored as xxxx - 1
+                0xF1..=0xFD => int_entry((enc & 0x0F) as i64 - 1),
+                _ => bail!("ziplist has an invalid entry encoding {:02X}", enc),
+            },
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// listpack: <total bytes u32><num elements u16> <entry>... 0xFF
+// entry: <encoding+data> <backlen>, backlen takes 1-5 bytes depending on the entry size
+pub fn decode_listpack(blob: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut c = Cursor::new(blob, "listpack");
+    c.skip(6)?;
+    let mut entries = Vec::new();
+    loop {
+        let start = c.pos;
+        let enc = c.u8()?;
+        if enc == 0xFF {
+            break;
+        }
+        let entry = if enc & 0x80 == 0 {
+            // 0xxxxxxx: 7 bit unsigned int
+            int_entry((enc & 0x7F) as i64)
+        } else if enc & 0xC0 == 0x80 {
+            // 10xxxxxx: string up to 63 bytes
+            c.take((enc & 0x3F) as usize)?.to_vec()
+        } else if enc & 0xE0 == 0xC0 {
+            // 110xxxxx yyyyyyyy: 13 bit signed int
+            let uv = (((enc & 0x1F) as i64) << 8) | c.u8()? as i64;
+            int_entry(if uv >= 1 << 12 { uv - (1 << 13) } else { uv })
+        } else if enc & 0xF0 == 0xE0 {
+            // 1110xxxx yyyyyyyy: string up to 4095 bytes
+            let len = (((enc & 0x0F) as usize) << 8) | c.u8()? as usize;
+            c.take(len)?.to_vec()
+        } else {
+            match enc {
+                0xF0 => {
+                    let len = c.u32_le()? as usize;
+                    c.take(len)?.to_vec()
+                }
+                0xF1 => int_entry(c.int_le(2)?),
+                0xF2 => int_entry(c.int_le(3)?),
+                0xF3 => int_entry(c.int_le(4)?),
+                0xF4 => int_entry(c.int_le(8)?),
+                _ => bail!("listpack has an invalid entry encoding {:02X}", enc),
+            }
+        };
+        let entry_len = c.pos - start;
+        c.skip(match entry_len {
+            0..=127 => 1,
+            128..=16383 => 2,
+            16384..=2097151 => 3,
+            2097152..=268435455 => 4,
+            _ => 5,
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// intset: <encoding u32: 2, 4 or 8><length u32> <little endian ints>
+pub fn decode_intset(blob: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut c = Cursor::new(blob, "intset");
+    let width = c.u32_le()? as usize;
+    if !matches!(width, 2 | 4 | 8) {
+        bail!("intset has an invalid encoding {}", width);
+    }
+    let len = c.u32_le()?;
+    (0..len).map(|_| c.int_le(width).map(int_entry)).collect()
+}
+
+// zipmap (hashes before Redis 2.6): <zmlen> (<len>key<len><free>value<free bytes>)... 0xFF
+// returns keys and values interleaved
+pub fn decode_zipmap(blob: &[u8]) -> Result<Vec<Vec<u8>>> {
+    fn len(c: &mut Cursor) -> Result<Option<usize>> {
+        match c.u8()? {
+            0xFF => Ok(None),
+            // ZIPMAP_BIGLEN: a 4 byte little endian length follows
+            254 => Ok(Some(c.u32_le()? as usize)),
+            n => Ok(Some(n as usize)),
+        }
+    }
+
+    let mut c = Cursor::new(blob, "zipmap");
+    c.skip(1)?;
+    let mut entries = Vec::new();
+    while let Some(klen) = len(&mut c)? {
+        entries.push(c.take(klen)?.to_vec());
+        let Some(vlen) = len(&mut c)? else {
+            bail!("zipmap ends after a key");
+        };
+        let free = c.u8()? as usize;
+        entries.push(c.take(vlen)?.to_vec());
+        c.skip(free)?;
+    }
+    Ok(entries)
+}
@@ -0,0 +1,56 @@
+use resp_protocol::Error;
+use thiserror::Error;
+
+// Errors a command reports back to the client. The message starts with the
+// standard Redis prefix (ERR, WRONGTYPE, ...) and is sent as a RESP error reply.
+#[derive(Debug, Error)]
+pub enum RedisError {
+    #[error("ERR unknown command '{0}', with args beginning with: {1}")]
+    UnknownCommand(String, String),
+    #[error("ERR unknown subcommand '{0}'. Try {1} HELP.")]
+    UnknownSubcommand(String, String),
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("MISCONF Errors writing to the AOF file: {0}")]
+    AofWrite(String),
+    #[error("ERR {0}")]
+    Other(String),
+}
+
+// longest part of a client argument quoted back in an error
+const MAX_ERROR_ARG_LEN: usize = 128;
+
+fn quoted_arg(arg: &[u8]) -> String {
+    String::from_utf8_lossy(&arg[..arg.len().min(MAX_ERROR_ARG_LEN)]).to_string()
+}
+
+impl RedisError {
+    pub fn unknown_command(args: &[bytes::Bytes]) -> Self {
+        let rest: String = args[1..]
+            .iter()
+            .map(|a| format!("'{}' ", quoted_arg(a)))
+            .collect();
+        RedisError::UnknownCommand(quoted_arg(&args[0]), rest)
+    }
+
+    pub fn to_reply(&self) -> Vec<u8> {
+        // an error reply is a single line, CR or LF from client data would end it early
+        // and let the rest be read as more replies
+        let msg = self.to_string().replace(['\r', '\n'], " ");
+        Error::new(msg.as_bytes()).bytes().to_vec()
+    }
+}
+
+// reply for any error returned by a command, errors that are not a RedisError become "ERR ..."
+pub fn error_reply(e: &anyhow::Error) -> Vec<u8> {
+    match e.downcast_ref::<RedisError>() {
+        Some(re) => re.to_reply(),
+        None => RedisError::Other(e.to_string()).to_reply(),
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use crate::server::Server;
+
+// Active expiration, modelled on Redis's activeExpireCycle: keys with a ttl are sampled
+// periodically and the expired ones deleted, so keys that are never read again still go away.
+
+// cycles per second
+const ACTIVE_EXPIRE_CYCLE_HZ: u64 = 10;
+// keys sampled per database in each round
+const ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP: usize = 20;
+// keep sampling a database while more than this percent of the sample was expired
+const ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE: usize = 10;
+// percent of each cycle period the sweep may spend
+const ACTIVE_EXPIRE_CYCLE_SLOW_TIME_PERC: u64 = 25;
+
+pub async fn run(server: Server) {
+    let mut interval = tokio::time::interval(Duration::from_millis(1000 / ACTIVE_EXPIRE_CYCLE_HZ));
+    loop {
+        interval.tick().await;
+        if server.active_expire.load(Ordering::Relaxed) {
+            active_expire_cycle(&server).await;
+        }
+    }
+}
+
+// one sweep over every database, run by `run` ACTIVE_EXPIRE_CYCLE_HZ times a second
+pub async fn active_expire_cycle(server: &Server) {
+    let time_limit = Duration::from_micros(
+        1_000_000 / ACTIVE_EXPIRE_CYCLE_HZ * ACTIVE_EXPIRE_CYCLE_SLOW_TIME_PERC / 100,
+    );
+    let start = Instant::now();
+    let mut total_sampled = 0;
+    let mut total_expired = 0;
+
+    let dbs: Vec<u64> = {
+        let storage = server.storage.lock().await;
+        storage.databases.iter().map(|e| *e.key()).collect()
+    };
+    'dbs: for db in dbs {
+        loop {
+            // the lock is released between rounds so clients are not starved
+            let (sampled, expired) = server
+                .storage
+                .lock()
+                .await
+                .expire_sample(db, ACTIVE_EXPIRE_CYCLE_KEYS_PER_LOOP)
+                .await;
+            total_sampled += sampled;
+            total_expired += expired;
+
+            if start.elapsed() > time_limit {
+                break 'dbs;
+            }
+            // few stale keys left in this database, move on
+            if sampled == 0 || expired * 100 <= sampled * ACTIVE_EXPIRE_CYCLE_ACCEPTABLE_STALE {
+                break;
+            }
+        }
+    }
+
+    if total_expired > 0 {
+        log::debug!(
+            "active expire cycle: sampled {} keys, expired {}",
+            total_sampled,
+            total_expired
+        );
+    }
+    server
+        .stats
+        .record_expire_cycle(total_sampled as u64, total_expired as u64);
+}
@@ -0,0 +1,153 @@
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use serde_json::{json, Value};
+
+use crate::db::{Expiry, KeyValue, RedisValue};
+
+// JSON form of keys and values, for dumping RDB contents and for loading them back.
+// Keys, members and values are binary: valid UTF-8 becomes a JSON string, anything else
+// {"hex": "..."}. Hashes and sorted sets are arrays of pairs, so neither binary fields
+// nor the stored order get lost. Infinite scores, which JSON numbers can't hold, are
+// written as "inf" and "-inf".
+
+pub fn bytes_to_json(b: &[u8]) -> Value {
+    match std::str::from_utf8(b) {
+        Ok(s) => Value::String(s.to_string()),
+        Err(_) => json!({ "hex": hex::encode(b) }),
+    }
+}
+
+fn score_to_json(score: f64) -> Value {
+    if score.is_finite() {
+        json!(score)
+    } else if score > 0.0 {
+        json!("inf")
+    } else if score < 0.0 {
+        json!("-inf")
+    } else {
+        json!("nan")
+    }
+}
+
+pub fn value_to_json(value: &RedisValue) -> Value {
+    match value {
+        RedisValue::String(s) => bytes_to_json(s),
+        RedisValue::List(items) | RedisValue::Set(items) => {
+            Value::Array(items.iter().map(|i| bytes_to_json(i)).collect())
+        }
+        RedisValue::SortedSet(members) => Value::Array(
+            members
+                .iter()
+                .map(|(m, score)| json!([bytes_to_json(m), score_to_json(*score)]))
+                .collect(),
+        ),
+        RedisValue::Hash(fields) => Value::Array(
+            fields
+                .iter()
+                .map(|(f, v)| json!([bytes_to_json(f), bytes_to_json(v)]))
+                .collect(),
+        ),
+    }
+}
+
+pub fn bytes_from_json(v: &Value) -> Result<Bytes> {
+    match v {
+        Value::String(s) => Ok(Bytes::from(s.clone())),
+        Value::Object(o) => match o.get("hex").and_then(|h| h.as_str()) {
+            Some(h) => Ok(Bytes::from(hex::decode(h).context("invalid hex string")?)),
+            None => bail!("expected a string or {{\"hex\": ...}}, got {}", v),
+        },
+        _ => bail!("expected a string or {{\"hex\": ...}}, got {}", v),
+    }
+}
+
+fn score_from_json(v: &Value) -> Result<f64> {
+    match v {
+        Value::Number(n) => n.as_f64().context("invalid score"),
+        Value::String(s) => match s.as_str() {
+            "inf" | "+inf" => Ok(f64::INFINITY),
+            "-inf" => Ok(f64::NEG_INFINITY),
+            "nan" => Ok(f64::NAN),
+            _ => bail!("invalid score {}", v),
+        },
+        _ => bail!("invalid score {}", v),
+    }
+}
+
+fn pairs_from_json(v: &Value) -> Result<Vec<(&Value, &Value)>> {
+    let Some(items) = v.as_array() else {
+        bail!("expected an array of pairs, got {}", v);
+    };
+    items
+        .iter()
+        .map(|p| match p.as_array().map(|p| p.as_slice()) {
+            Some([a, b]) => Ok((a, b)),
+            _ => bail!("expected a pair, got {}", p),
+        })
+        .collect()
+}
+
+// a value of type `type_name` (as returned by RedisValue::type_name)
+pub fn value_from_json(type_name: &str, v: &Value) -> Result<RedisValue> {
+    let list = |v: &Value| -> Result<Vec<Bytes>> {
+        match v.as_array() {
+            Some(items) => items.iter().map(bytes_from_json).collect(),
+            None => bail!("expected an array, got {}", v),
+        }
+    };
+    Ok(match type_name {
+        "string" => RedisValue::String(bytes_from_json(v)?),
+        "list" => RedisValue::List(list(v)?),
+        "set" => RedisValue::Set(list(v)?),
+        "zset" => RedisValue::SortedSet(
+            pairs_from_json(v)?
+                .into_iter()
+                .map(|(m, score)| Ok((bytes_from_json(m)?, score_from_json(score)?)))
+                .collect::<Result<_>>()?,
+        ),
+        "hash" => RedisValue::Hash(
+            pairs_from_json(v)?
+                .into_iter()
+                .map(|(f, v)| Ok((bytes_from_json(f)?, bytes_from_json(v)?)))
+                .collect::<Result<_>>()?,
+        ),
+        _ => bail!("unknown type {}", type_name),
+    })
+}
+
+// One key as a JSON record:
+//   {"db": 0, "key": "k", "type": "hash", "ttl": 1500, "value": [["f", "v"]]}
+// ttl is in milliseconds from `now`, -1 for keys without an expire.
+pub fn key_to_json(db: u64, key: &[u8], kv: &KeyValue, now: u64) -> Value {
+    json!({
+        "db": db,
+        "key": bytes_to_json(key),
+        "type": kv.value.type_name(),
+        "ttl": kv.expiry.map_or(-1, |e| e.ttl_millis(now) as i64),
+        "value": value_to_json(&kv.value),
+    })
+}
+
+// a record written by key_to_json, "db" and "ttl" may be left out
+pub fn key_from_json(record: &Value, now: u64) -> Result<(u64, Bytes, KeyValue)> {
+    let field = |name: &str| {
+        record
+            .get(name)
+            .with_context(|| format!("missing \"{}\"", name))
+    };
+    let db = match record.get("db") {
+        Some(db) => db.as_u64().context("\"db\" must be a database index")?,
+        None => 0,
+    };
+    let key = bytes_from_json(field("key")?)?;
+    let Some(type_name) = field("type")?.as_str() else {
+        bail!("\"type\" must be a string");
+    };
+    let value = value_from_json(type_name, field("value")?)?;
+    let expiry = match record.get("ttl").map(|t| t.as_i64()) {
+        None | Some(Some(-1)) => None,
+        Some(Some(ttl)) if ttl >= 0 => Some(Expiry::from_millis(now + ttl as u64)),
+        Some(_) => bail!("\"ttl\" must be -1 or a number of milliseconds"),
+    };
+    Ok((db, key, KeyValue { value, expiry }))
+}
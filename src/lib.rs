@@ -0,0 +1,15 @@
+pub mod aof;
+pub mod clock;
+pub mod command_table;
+pub mod commands;
+pub mod crc64;
+pub mod db;
+pub mod encodings;
+pub mod error;
+pub mod expire;
+pub mod json;
+pub mod lzf;
+pub mod persistence;
+pub mod replication;
+pub mod resp;
+pub mod server;
@@ -0,0 +1,143 @@
+use anyhow::{bail, Result};
+
+// LZF, the compression Redis uses for RDB strings (rdbcompression yes).
+// The stream is a sequence of chunks, each starting with a control byte:
+//   000LLLLL                    literal run of L+1 bytes
+//   LLLooooo [LLLLLLLL] oooooooo back reference: copy len+2 bytes from `offset+1` bytes back,
+//                                a length of 7 in the top bits continues in an extra byte
+
+const HASH_LOG: usize = 14;
+const MAX_LIT: usize = 1 << 5;
+const MAX_OFF: usize = 1 << 13;
+const MAX_REF: usize = (1 << 8) + (1 << 3);
+
+pub fn decompress(input: &[u8], out_len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(out_len);
+    let mut ip = 0;
+    while ip < input.len() {
+        let ctrl = input[ip] as usize;
+        ip += 1;
+        if ctrl < MAX_LIT {
+            let run = ctrl + 1;
+            if ip + run > input.len() || out.len() + run > out_len {
+                bail!("lzf: literal run past the end of the data");
+            }
+            out.extend_from_slice(&input[ip..ip + run]);
+            ip += run;
+            continue;
+        }
+
+        let mut len = ctrl >> 5;
+        if len == 7 {
+            let Some(b) = input.get(ip) else {
+                bail!("lzf: truncated back reference");
+            };
+            len += *b as usize;
+            ip += 1;
+        }
+        let Some(b) = input.get(ip) else {
+            bail!("lzf: truncated back reference");
+        };
+        ip += 1;
+        let back = (((ctrl & 0x1F) << 8) | *b as usize) + 1;
+        len += 2;
+        if back > out.len() || out.len() + len > out_len {
+            bail!("lzf: invalid back reference");
+        }
+        // the source may overlap what is being written, copy byte by byte
+        let start = out.len() - back;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+    if out.len() != out_len {
+        bail!(
+            "lzf: decompressed {} bytes, expected {}",
+            out.len(),
+            out_len
+        );
+    }
+    Ok(out)
+}
+
+fn hash(b: &[u8]) -> usize {
+    let v = ((b[0] as usize) << 16) | ((b[1] as usize) << 8) | b[2] as usize;
+    (v.wrapping_mul(2654435761) >> 8) & ((1 << HASH_LOG) - 1)
+}
+
+// Compress `input`, `None` when the result would not be smaller
+pub fn compress(input: &[u8]) -> Option<Vec<u8>> {
+    let n = input.len();
+    if n < 4 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(n);
+    let mut table = vec![usize::MAX; 1 << HASH_LOG];
+    // position of the control byte of the current literal run, and its length
+    let mut lit_ctrl = 0;
+    let mut lit = 0;
+    out.push(0);
+
+    let mut ip = 0;
+    while ip < n {
+        if ip + 2 < n {
+            let h = hash(&input[ip..]);
+            let r = table[h];
+            table[h] = ip;
+            if r != usize::MAX && ip - r <= MAX_OFF && input[r..r + 3] == input[ip..ip + 3] {
+                let max = (n - ip).min(MAX_REF);
+                let mut len = 3;
+                while len < max && input[r + len] == input[ip + len] {
+                    len += 1;
+                }
+
+                // close the literal run, or drop its unused control byte
+                if lit == 0 {
+                    out.pop();
+                } else {
+                    out[lit_ctrl] = (lit - 1) as u8;
+                }
+                let off = ip - r - 1;
+                let l = len - 2;
+                if l < 7 {
+                    out.push(((l << 5) | (off >> 8)) as u8);
+                } else {
+                    out.push(((7 << 5) | (off >> 8)) as u8);
+                    out.push((l - 7) as u8);
+                }
+                out.push(off as u8);
+                ip += len;
+
+                lit_ctrl = out.len();
+                lit = 0;
+                out.push(0);
+                if out.len() >= n {
+                    return None;
+                }
+                continue;
+            }
+        }
+
+        out.push(input[ip]);
+        lit += 1;
+        ip += 1;
+        if lit == MAX_LIT {
+            out[lit_ctrl] = (MAX_LIT - 1) as u8;
+            lit_ctrl = out.len();
+            lit = 0;
+            out.push(0);
+        }
+        if out.len() >= n {
+            return None;
+        }
+    }
+    if lit == 0 {
+        out.pop();
+    } else {
+        out[lit_ctrl] = (lit - 1) as u8;
+    }
+    if out.len() >= n {
+        return None;
+    }
+    Some(out)
+}
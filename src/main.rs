@@ -1,17 +1,12 @@
 use tokio::net::TcpListener;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use std::sync::Arc;
 use tklog::{error, info, Format, LEVEL, LOG};
 
 use clap::Parser;
 
-use crate::server::ServerOpt;
-
-mod commands;
-mod db;
-mod replication;
-mod server;
+use codecrafters_redis::{aof, db, persistence, server, server::ServerOpt};
 
 #[derive(Parser, Debug)]
 #[command(version)]
@@ -30,6 +25,55 @@ struct Args {
     // start a slave replication for the master
     #[arg(short, long, default_value = "")]
     replicaof: String,
+
+    // load an rdb file even when its checksum doesn't match
+    #[arg(long)]
+    rdb_skip_checksum: bool,
+
+    // compress long strings in rdb snapshots with LZF
+    #[arg(long, default_value = "yes", value_parser = ["yes", "no"])]
+    rdbcompression: String,
+
+    // snapshot rules "<seconds> <changes> ...", "" disables automatic saving
+    #[arg(long, default_value = persistence::DEFAULT_SAVE_PARAMS)]
+    save: String,
+
+    // largest bulk string accepted from clients and rdb files, e.g. "512mb", at least 1mb
+    #[arg(long, default_value = "512mb", value_parser = server::parse_proto_max_bulk_len)]
+    proto_max_bulk_len: u64,
+
+    // number of databases, SELECT takes an index in 0..databases
+    #[arg(long, default_value_t = server::DEFAULT_DATABASES, value_parser = clap::value_parser!(u64).range(1..))]
+    databases: u64,
+
+    // log every write command to an append-only file
+    #[arg(long, default_value = "no", value_parser = ["yes", "no"])]
+    appendonly: String,
+
+    // when the append-only file is fsynced
+    #[arg(long, default_value = "everysec", value_parser = ["always", "everysec", "no"])]
+    appendfsync: String,
+
+    // append-only file name, under --dir
+    #[arg(long, default_value = aof::DEFAULT_APPENDFILENAME)]
+    appendfilename: String,
+
+    // directory under --dir holding the append-only file parts and their manifest
+    #[arg(long, default_value = aof::DEFAULT_APPENDDIRNAME)]
+    appenddirname: String,
+
+    // rewrite the append-only file once it grew this many percent since the last rewrite,
+    // 0 disables automatic rewrites
+    #[arg(long, default_value_t = aof::DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE)]
+    auto_aof_rewrite_percentage: u64,
+
+    // smallest append-only file size an automatic rewrite starts at, e.g. "64mb"
+    #[arg(long, default_value = "64mb", value_parser = server::parse_memory)]
+    auto_aof_rewrite_min_size: u64,
+
+    // load an append-only file whose last command was cut short, dropping that command
+    #[arg(long, default_value = "yes", value_parser = ["yes", "no"])]
+    aof_load_truncated: String,
 }
 
 #[tokio::main]
@@ -51,29 +95,44 @@ async fn main() -> Result<()> {
     let listener = TcpListener::bind(url).await.unwrap();
 
     // create a db in memory
+    // an empty dir or dbfilename falls back to "." and "dump.rdb"
     let mut db_conf = db::Dbconf::new();
-    if !args.dir.is_empty() && !args.dbfilename.is_empty() {
-        db_conf.set(args.dir.clone(), args.dbfilename.clone());
-    }
+    db_conf.set(args.dir.clone(), args.dbfilename.clone());
+
+    let save_params = persistence::parse_save_params(&args.save)?;
 
     //--replicaof "localhost 6379"
     let rep: Vec<&str> = args.replicaof.split_whitespace().collect();
 
-    let s_opt = if rep.len() < 2 || rep[1].parse::<u32>().is_err() {
+    let mut s_opt = if rep.len() < 2 || rep[1].parse::<u32>().is_err() {
         log::debug!(" no replicaof's arguments !!! Create a Master");
-        ServerOpt::new(args.port, db_conf, None, true)
+        ServerOpt::new(args.port, db_conf, None, save_params, true)
     } else {
         ServerOpt::new(
             args.port,
             db_conf,
             Some((rep[0].to_lowercase(), rep[1].to_lowercase())),
+            save_params,
             false,
         )
     };
 
+    s_opt.rdb_skip_checksum = args.rdb_skip_checksum;
+    s_opt.rdb_compression = args.rdbcompression == "yes";
+    s_opt.proto_max_bulk_len = args.proto_max_bulk_len;
+    s_opt.databases = args.databases;
+    s_opt.appendonly = args.appendonly == "yes";
+    s_opt.appendfsync =
+        aof::AppendFsync::parse(&args.appendfsync).unwrap_or(aof::AppendFsync::EverySec);
+    s_opt.appendfilename = args.appendfilename;
+    s_opt.appenddirname = args.appenddirname;
+    s_opt.auto_aof_rewrite_percentage = args.auto_aof_rewrite_percentage;
+    s_opt.auto_aof_rewrite_min_size = args.auto_aof_rewrite_min_size;
+    s_opt.aof_load_truncated = args.aof_load_truncated == "yes";
+
     let mut server = server::Server::new(s_opt)
         .await
-        .expect("create server error");
+        .context("create server error")?;
 
     server.start(listener).await
 }
@@ -0,0 +1,301 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use dashmap::DashMap;
+use tklog::{error, info};
+use tokio::{fs, io::BufWriter};
+
+use crate::{
+    db::{Dbconf, RdbFile, RdbWriter},
+    error::RedisError,
+    server::Server,
+};
+
+// RDB snapshots: SAVE writes on the request path, BGSAVE writes a copy of the keyspace
+// from a background task. Either way the file is written under a temporary name and
+// renamed over dir/dbfilename, so a crash mid-save never leaves a truncated dump behind.
+
+// how often the save rules are checked
+const SAVE_CRON_HZ: u64 = 10;
+// wait this many seconds after a failed BGSAVE before a save rule may trigger another one
+const BGSAVE_RETRY_DELAY: u64 = 5;
+// Redis's default: after 3600s if 1 key changed, 300s if 100 changed, 60s if 10000 changed
+pub const DEFAULT_SAVE_PARAMS: &str = "3600 1 300 100 60 10000";
+
+// A "save <seconds> <changes>" rule: snapshot once at least `changes` writes happened
+// and `seconds` passed since the last successful save.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SaveParam {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl fmt::Display for SaveParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.seconds, self.changes)
+    }
+}
+
+// "3600 1 300 100": pairs of seconds and changes, an empty string disables snapshotting
+pub fn parse_save_params(s: &str) -> Result<Vec<SaveParam>> {
+    let nums = s
+        .split_whitespace()
+        .map(|n| n.parse::<u64>())
+        .collect::<Result<Vec<u64>, _>>();
+    match nums {
+        Ok(nums) if nums.len() % 2 == 0 => Ok(nums
+            .chunks(2)
+            .map(|p| SaveParam {
+                seconds: p[0],
+                changes: p[1],
+            })
+            .collect()),
+        _ => bail!(RedisError::Other("Invalid save parameters".to_string())),
+    }
+}
+
+pub fn format_save_params(params: &[SaveParam]) -> String {
+    params
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// state reported in the "persistence" section of INFO and by LASTSAVE
+#[derive(Debug)]
+pub struct PersistenceState {
+    // unix time in seconds of the last successful save
+    last_save: AtomicU64,
+    // unix time in seconds of the last BGSAVE attempt
+    last_bgsave_try: AtomicU64,
+    bgsave_in_progress: AtomicBool,
+    last_bgsave_ok: AtomicBool,
+    // changed by CONFIG SET save
+    save_params: RwLock<Vec<SaveParam>>,
+    // rdbcompression: LZF-compress long strings in snapshots
+    rdb_compression: AtomicBool,
+}
+
+impl PersistenceState {
+    pub fn new(now_secs: u64, save_params: Vec<SaveParam>, rdb_compression: bool) -> Self {
+        PersistenceState {
+            last_save: AtomicU64::new(now_secs),
+            last_bgsave_try: AtomicU64::new(0),
+            bgsave_in_progress: AtomicBool::new(false),
+            last_bgsave_ok: AtomicBool::new(true),
+            save_params: RwLock::new(save_params),
+            rdb_compression: AtomicBool::new(rdb_compression),
+        }
+    }
+
+    pub fn last_save(&self) -> u64 {
+        self.last_save.load(Ordering::Relaxed)
+    }
+
+    pub fn save_params(&self) -> Vec<SaveParam> {
+        self.save_params
+            .read()
+            .expect("save params lock poisoned")
+            .clone()
+    }
+
+    pub fn set_save_params(&self, params: Vec<SaveParam>) {
+        *self.save_params.write().expect("save params lock poisoned") = params;
+    }
+
+    pub fn rdb_compression(&self) -> bool {
+        self.rdb_compression.load(Ordering::Relaxed)
+    }
+
+    pub fn set_rdb_compression(&self, enabled: bool) {
+        self.rdb_compression.store(enabled, Ordering::Relaxed);
+    }
+
+    // the first rule that calls for a snapshot now
+    fn due_save_param(&self, dirty: u64, now_secs: u64) -> Option<SaveParam> {
+        // after a failed BGSAVE, give the disk some time before trying again
+        if !self.last_bgsave_ok.load(Ordering::Relaxed)
+            && now_secs.saturating_sub(self.last_bgsave_try.load(Ordering::Relaxed))
+                <= BGSAVE_RETRY_DELAY
+        {
+            return None;
+        }
+        let since_save = now_secs.saturating_sub(self.last_save());
+        self.save_params
+            .read()
+            .expect("save params lock poisoned")
+            .iter()
+            .find(|p| dirty >= p.changes && since_save >= p.seconds)
+            .copied()
+    }
+
+    pub fn to_info(&self, dirty: u64) -> DashMap<String, String> {
+        let section = DashMap::new();
+        section.insert("rdb_changes_since_last_save".to_string(), dirty.to_string());
+        section.insert(
+            "rdb_bgsave_in_progress".to_string(),
+            (self.bgsave_in_progress.load(Ordering::Relaxed) as u8).to_string(),
+        );
+        section.insert(
+            "rdb_last_save_time".to_string(),
+            self.last_save().to_string(),
+        );
+        section.insert(
+            "rdb_last_bgsave_status".to_string(),
+            if self.last_bgsave_ok.load(Ordering::Relaxed) {
+                "ok"
+            } else {
+                "err"
+            }
+            .to_string(),
+        );
+        section
+    }
+}
+
+// dir/dbfilename, with Redis's defaults for the parts that are not configured
+pub fn rdb_path(conf: &Dbconf) -> PathBuf {
+    let mut path = match conf.get_dir().as_str() {
+        "" => PathBuf::from("."),
+        dir => PathBuf::from(dir),
+    };
+    match conf.get_db_filename().as_str() {
+        "" => path.push("dump.rdb"),
+        name => path.push(name),
+    }
+    path
+}
+
+// write `rdb` to a temp file next to `path`, then rename it into place
+pub async fn write_rdb(rdb: &RdbFile, path: &Path, compression: bool) -> Result<()> {
+    let tmp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let file = fs::File::create(&tmp)
+        .await
+        .with_context(|| format!("failed opening {} for saving", tmp.display()))?;
+    let mut writer = BufWriter::new(file);
+    let written = RdbWriter::new(&mut writer)
+        .compression(compression)
+        .write(rdb)
+        .await;
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    writer.into_inner().sync_all().await?;
+    fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("failed renaming {} to {}", tmp.display(), path.display()))?;
+    Ok(())
+}
+
+// SAVE: the keyspace stays locked until the dump is on disk
+pub async fn save(server: &Server) -> Result<()> {
+    if server
+        .persistence
+        .bgsave_in_progress
+        .load(Ordering::Relaxed)
+    {
+        bail!(RedisError::Other(
+            "Background save already in progress".to_string()
+        ));
+    }
+    let path = rdb_path(&server.option.db_conf);
+    let mut storage = server.storage.lock().await;
+    let compression = server.persistence.rdb_compression();
+    if let Err(e) = write_rdb(&storage, &path, compression).await {
+        error!("error saving DB on disk: {}", e);
+        bail!(e);
+    }
+    let dirty = storage.dirty();
+    storage.clear_dirty(dirty);
+    server
+        .persistence
+        .last_save
+        .store(server.clock.now_millis() / 1000, Ordering::Relaxed);
+    info!("DB saved on disk");
+    Ok(())
+}
+
+// BGSAVE: copy the keyspace and write the copy from a background task
+pub async fn bgsave(server: &Server) -> Result<()> {
+    if server
+        .persistence
+        .bgsave_in_progress
+        .swap(true, Ordering::SeqCst)
+    {
+        bail!(RedisError::Other(
+            "Background save already in progress".to_string()
+        ));
+    }
+    server
+        .persistence
+        .last_bgsave_try
+        .store(server.clock.now_millis() / 1000, Ordering::Relaxed);
+    let (snapshot, dirty) = {
+        let storage = server.storage.lock().await;
+        (storage.clone(), storage.dirty())
+    };
+    let path = rdb_path(&server.option.db_conf);
+    let compression = server.persistence.rdb_compression();
+    let server = server.clone();
+    tokio::spawn(async move {
+        let state = &server.persistence;
+        match write_rdb(&snapshot, &path, compression).await {
+            Ok(()) => {
+                server.storage.lock().await.clear_dirty(dirty);
+                state
+                    .last_save
+                    .store(server.clock.now_millis() / 1000, Ordering::Relaxed);
+                state.last_bgsave_ok.store(true, Ordering::Relaxed);
+                info!("Background saving terminated with success");
+            }
+            Err(e) => {
+                state.last_bgsave_ok.store(false, Ordering::Relaxed);
+                error!("Background saving error: {}", e);
+            }
+        }
+        state.bgsave_in_progress.store(false, Ordering::SeqCst);
+    });
+    Ok(())
+}
+
+// Checks the save rules periodically and starts a BGSAVE when one of them matches
+pub async fn run(server: Server) {
+    let mut interval = tokio::time::interval(Duration::from_millis(1000 / SAVE_CRON_HZ));
+    loop {
+        interval.tick().await;
+        // the final snapshot of a shutdown is taken by the shutdown itself
+        if server.is_shutting_down()
+            || server
+                .persistence
+                .bgsave_in_progress
+                .load(Ordering::Relaxed)
+        {
+            continue;
+        }
+        let dirty = server.storage.lock().await.dirty();
+        let now_secs = server.clock.now_millis() / 1000;
+        if let Some(p) = server.persistence.due_save_param(dirty, now_secs) {
+            info!("{} changes in {} seconds. Saving...", p.changes, p.seconds);
+            if let Err(e) = bgsave(&server).await {
+                error!("automatic BGSAVE failed to start: {}", e);
+            }
+        }
+    }
+}
+
+// wait for a running BGSAVE to finish
+pub async fn wait_bgsave(server: &Server) {
+    while server.persistence.bgsave_in_progress.load(Ordering::SeqCst) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
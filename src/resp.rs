@@ -0,0 +1,279 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, Bytes, BytesMut};
+
+// initial capacity of a connection's read buffer, it grows on demand
+pub const BUF_SIZE: usize = 4096;
+// largest bulk string accepted by default (proto-max-bulk-len), same as Redis
+pub const DEFAULT_PROTO_MAX_BULK_LEN: u64 = 512 * 1024 * 1024;
+// CONFIG SET proto-max-bulk-len doesn't go below 1mb
+pub const MIN_PROTO_MAX_BULK_LEN: u64 = 1024 * 1024;
+// longest inline request or "*"/"$" header line, more without a CRLF is a protocol error
+const PROTO_INLINE_MAX_SIZE: usize = 64 * 1024;
+
+// RESP type prefixes
+const SIMPLE_STRING: u8 = b'+';
+const ERROR: u8 = b'-';
+const INTEGER: u8 = b':';
+const BULK_STRING: u8 = b'$';
+const ARRAY: u8 = b'*';
+
+// A decoded RESP value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Simple(Bytes),
+    Error(Bytes),
+    Integer(i64),
+    Bulk(Bytes),
+    Null,
+    Array(Vec<Frame>),
+}
+
+impl Frame {
+    // A command is an array of bulk strings (or an inline command, decoded the same way)
+    pub fn into_args(self) -> Result<Vec<Bytes>> {
+        match self {
+            Frame::Array(items) => items
+                .into_iter()
+                .map(|f| match f {
+                    Frame::Bulk(b) => Ok(b),
+                    other => bail!("Protocol error: unexpected {:?} in command", other),
+                })
+                .collect(),
+            other => bail!("Protocol error: expected a command array, got {:?}", other),
+        }
+    }
+}
+
+// Incremental RESP decoder.
+//
+// Bytes read from a socket are appended to the internal buffer; `decode` hands back one
+// complete frame at a time and keeps any partial tail until the rest of it arrives.
+// A multibulk is only made of bulk strings, as in Redis, and the elements that already
+// arrived are kept between calls, so a large command received in many reads is parsed once.
+#[derive(Debug)]
+pub struct RespDecoder {
+    buf: BytesMut,
+    // a longer bulk string is a protocol error
+    max_bulk_len: u64,
+    // the multibulk being received, if its header was read
+    multibulk: Option<Multibulk>,
+}
+
+#[derive(Debug)]
+struct Multibulk {
+    items: Vec<Frame>,
+    // elements still to come
+    remaining: usize,
+    // where the next element starts in the buffer, which is consumed once the command is complete
+    pos: usize,
+}
+
+impl Default for RespDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RespDecoder {
+    pub fn new() -> Self {
+        RespDecoder {
+            buf: BytesMut::with_capacity(BUF_SIZE),
+            max_bulk_len: DEFAULT_PROTO_MAX_BULK_LEN,
+            multibulk: None,
+        }
+    }
+
+    pub fn set_max_bulk_len(&mut self, max: u64) {
+        self.max_bulk_len = max;
+    }
+
+    // buffer to read socket data into, e.g. `stream.read_buf(decoder.buffer_mut())`
+    pub fn buffer_mut(&mut self) -> &mut BytesMut {
+        &mut self.buf
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    // bytes not decoded yet
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    // Take the next complete frame. `Ok(None)` means more bytes are needed.
+    pub fn decode(&mut self) -> Result<Option<Frame>> {
+        let mut mb = match self.multibulk.take() {
+            Some(mb) => mb,
+            None => {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                if self.buf[0] != ARRAY {
+                    return Ok(match parse_frame(&self.buf, 0, self.max_bulk_len)? {
+                        Some((frame, end)) => {
+                            self.buf.advance(end);
+                            Some(frame)
+                        }
+                        None => None,
+                    });
+                }
+                let Some((line, next)) = read_header(&self.buf, 1, "mbulk count string")? else {
+                    return Ok(None);
+                };
+                let count = parse_len(line)?;
+                if count == -1 {
+                    self.buf.advance(next);
+                    return Ok(Some(Frame::Null));
+                }
+                if count < 0 || count > i32::MAX as i64 {
+                    bail!("Protocol error: invalid multibulk length");
+                }
+                Multibulk {
+                    items: Vec::with_capacity(count.min(1024) as usize),
+                    remaining: count as usize,
+                    pos: next,
+                }
+            }
+        };
+
+        while mb.remaining > 0 {
+            let Some(&prefix) = self.buf.get(mb.pos) else {
+                self.multibulk = Some(mb);
+                return Ok(None);
+            };
+            if prefix != BULK_STRING {
+                bail!(
+                    "Protocol error: expected '$', got '{}'",
+                    (prefix as char).escape_default()
+                );
+            }
+            match parse_frame(&self.buf, mb.pos, self.max_bulk_len)? {
+                Some((item, next)) => {
+                    mb.items.push(item);
+                    mb.remaining -= 1;
+                    mb.pos = next;
+                }
+                None => {
+                    self.multibulk = Some(mb);
+                    return Ok(None);
+                }
+            }
+        }
+        self.buf.advance(mb.pos);
+        Ok(Some(Frame::Array(mb.items)))
+    }
+
+    // Take the rdb payload of a full resync: "$<len>\r\n" followed by the raw file,
+    // which (unlike a bulk string) has no trailing CRLF.
+    pub fn decode_rdb(&mut self) -> Result<Option<Bytes>> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        if self.buf[0] != BULK_STRING {
+            bail!("Protocol error: expected '$' before the rdb payload");
+        }
+        let Some((line, start)) = read_header(&self.buf, 1, "bulk count string")? else {
+            return Ok(None);
+        };
+        let len = parse_len(line)?;
+        if len < 0 {
+            bail!("Protocol error: invalid rdb payload length");
+        }
+        let end = start + len as usize;
+        if self.buf.len() < end {
+            return Ok(None);
+        }
+        self.buf.advance(start);
+        Ok(Some(self.buf.split_to(len as usize).freeze()))
+    }
+}
+
+// returns the line starting at `pos` (without CRLF) and the position after the CRLF
+fn read_line(buf: &[u8], pos: usize) -> Option<(&[u8], usize)> {
+    buf[pos..]
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .map(|i| (&buf[pos..pos + i], pos + i + 2))
+}
+
+// read_line for a line a client may send without ever ending it: past
+// PROTO_INLINE_MAX_SIZE bytes without a CRLF it is "too big <what>"
+fn read_header<'a>(buf: &'a [u8], pos: usize, what: &str) -> Result<Option<(&'a [u8], usize)>> {
+    match read_line(buf, pos) {
+        Some(line) => Ok(Some(line)),
+        None if buf.len() - pos > PROTO_INLINE_MAX_SIZE => {
+            bail!("Protocol error: too big {}", what)
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_len(line: &[u8]) -> Result<i64> {
+    match std::str::from_utf8(line).ok().and_then(|s| s.parse().ok()) {
+        Some(n) => Ok(n),
+        None => bail!(
+            "Protocol error: invalid length {:?}",
+            String::from_utf8_lossy(line)
+        ),
+    }
+}
+
+// Parse one frame other than a multibulk at `pos`. Returns the frame and the position
+// right after it, or `None` when the buffer ends before the frame does.
+// A bulk length over `max_bulk_len` is rejected as soon as its header is read.
+fn parse_frame(buf: &[u8], pos: usize, max_bulk_len: u64) -> Result<Option<(Frame, usize)>> {
+    if pos >= buf.len() {
+        return Ok(None);
+    }
+    let prefix = buf[pos];
+    if !matches!(prefix, SIMPLE_STRING | ERROR | INTEGER | BULK_STRING) {
+        return parse_inline(buf, pos);
+    }
+
+    let what = match prefix {
+        BULK_STRING => "bulk count string",
+        _ => "inline request",
+    };
+    let Some((line, next)) = read_header(buf, pos + 1, what)? else {
+        return Ok(None);
+    };
+    match prefix {
+        SIMPLE_STRING => Ok(Some((Frame::Simple(Bytes::copy_from_slice(line)), next))),
+        ERROR => Ok(Some((Frame::Error(Bytes::copy_from_slice(line)), next))),
+        INTEGER => Ok(Some((Frame::Integer(parse_len(line)?), next))),
+        _ => {
+            let len = parse_len(line)?;
+            if len == -1 {
+                return Ok(Some((Frame::Null, next)));
+            }
+            if len < 0 || len as u64 > max_bulk_len {
+                bail!("Protocol error: invalid bulk length");
+            }
+            let end = next + len as usize;
+            if buf.len() < end + 2 {
+                return Ok(None);
+            }
+            if &buf[end..end + 2] != b"\r\n" {
+                bail!("Protocol error: bulk string is not terminated by CRLF");
+            }
+            Ok(Some((
+                Frame::Bulk(Bytes::copy_from_slice(&buf[next..end])),
+                end + 2,
+            )))
+        }
+    }
+}
+
+// inline commands ("PING\r\n") as sent by telnet or redis-cli in raw mode
+fn parse_inline(buf: &[u8], pos: usize) -> Result<Option<(Frame, usize)>> {
+    let Some((line, next)) = read_header(buf, pos, "inline request")? else {
+        return Ok(None);
+    };
+    let items = line
+        .split(|c| c.is_ascii_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| Frame::Bulk(Bytes::copy_from_slice(s)))
+        .collect();
+    Ok(Some((Frame::Array(items), next)))
+}
@@ -1,31 +1,66 @@
-use std::{fs, path::PathBuf, sync::Arc};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use crate::{
+    aof::{self, Aof, AppendFsync},
+    clock::{Clock, SystemClock},
+    commands::ClientState,
     db::{Dbconf, RdbFile, RdbParser, RDB_VERSION},
+    error::{error_reply, RedisError},
+    persistence::{self, PersistenceState, SaveParam},
     replication::{Replication, ReplicationSet},
+    resp::{Frame, RespDecoder, DEFAULT_PROTO_MAX_BULK_LEN, MIN_PROTO_MAX_BULK_LEN},
 };
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use dashmap::DashMap;
 use rand::rng;
 use rand::{distr::Alphabetic, Rng};
-use resp_protocol::{Array, ArrayBuilder, BulkString, SimpleString};
+use resp_protocol::{ArrayBuilder, BulkString};
 use tklog::{error, info};
 use tokio::{
     fs::File,
     io::{AsyncReadExt, AsyncWriteExt, Interest},
     net::{TcpListener, TcpStream},
-    sync::Mutex,
-    sync::RwLock,
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, watch, Mutex},
 };
 
-use crate::commands;
-const BUF_SIZE: usize = 100;
+use crate::{commands, expire};
+
+// number of databases when --databases is not given
+pub const DEFAULT_DATABASES: u64 = 16;
 
 #[derive(Clone, Debug)]
 pub struct ServerOpt {
     pub port: String,
     pub db_conf: Dbconf,
     pub replicaof: Option<(String, String)>,
+    // snapshotting rules from --save
+    pub save_params: Vec<SaveParam>,
+    // --rdb-skip-checksum
+    pub rdb_skip_checksum: bool,
+    // --rdbcompression
+    pub rdb_compression: bool,
+    // --proto-max-bulk-len
+    pub proto_max_bulk_len: u64,
+    // --databases: SELECT accepts 0..databases
+    pub databases: u64,
+    // --appendonly, --appendfsync, --appendfilename, --appenddirname
+    pub appendonly: bool,
+    pub appendfsync: AppendFsync,
+    pub appendfilename: String,
+    pub appenddirname: String,
+    // --auto-aof-rewrite-percentage, --auto-aof-rewrite-min-size
+    pub auto_aof_rewrite_percentage: u64,
+    pub auto_aof_rewrite_min_size: u64,
+    // --aof-load-truncated
+    pub aof_load_truncated: bool,
     master_replid: String,
     master_repl_offset: u32,
     pub is_master: bool,
@@ -36,6 +71,7 @@ impl ServerOpt {
         port: String,
         db_conf: Dbconf,
         replicaof: Option<(String, String)>,
+        save_params: Vec<SaveParam>,
         is_master: bool,
     ) -> Self {
         let replid = rng()
@@ -47,6 +83,18 @@ impl ServerOpt {
             port: port,
             db_conf: db_conf,
             replicaof: replicaof,
+            save_params,
+            rdb_skip_checksum: false,
+            rdb_compression: true,
+            proto_max_bulk_len: DEFAULT_PROTO_MAX_BULK_LEN,
+            databases: DEFAULT_DATABASES,
+            appendonly: false,
+            appendfsync: AppendFsync::EverySec,
+            appendfilename: aof::DEFAULT_APPENDFILENAME.to_string(),
+            appenddirname: aof::DEFAULT_APPENDDIRNAME.to_string(),
+            auto_aof_rewrite_percentage: aof::DEFAULT_AUTO_AOF_REWRITE_PERCENTAGE,
+            auto_aof_rewrite_min_size: aof::DEFAULT_AUTO_AOF_REWRITE_MIN_SIZE,
+            aof_load_truncated: true,
             master_replid: replid,
             master_repl_offset: 0,
             is_master,
@@ -63,25 +111,130 @@ impl ServerOpt {
     }
 }
 
+// A memory size as written in Redis configs: "1024", "64kb", "512mb", "1gb".
+// k/m/g are powers of 1000, kb/mb/gb powers of 1024.
+pub fn parse_memory(s: &str) -> Result<u64> {
+    let lower = s.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit: u64 = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => bail!("invalid memory unit in '{}'", s),
+    };
+    match digits.parse::<u64>().ok().and_then(|n| n.checked_mul(unit)) {
+        Some(n) => Ok(n),
+        None => bail!("invalid memory size '{}'", s),
+    }
+}
+
+// --proto-max-bulk-len and CONFIG SET proto-max-bulk-len: a size parse_memory accepts,
+// between 1mb and i64::MAX like in Redis
+pub fn parse_proto_max_bulk_len(s: &str) -> Result<u64> {
+    let n = parse_memory(s)?;
+    if !(MIN_PROTO_MAX_BULK_LEN..=i64::MAX as u64).contains(&n) {
+        bail!(
+            "argument must be between {} and {} inclusive",
+            MIN_PROTO_MAX_BULK_LEN,
+            i64::MAX
+        );
+    }
+    Ok(n)
+}
+
+// counters reported in the "stats" section of INFO
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    expired_keys: AtomicU64,
+    // running estimate of the percentage of keys that are expired but not yet deleted,
+    // stored as f64 bits
+    expired_stale_perc: AtomicU64,
+}
+
+impl ServerStats {
+    // keys deleted on access because their ttl had passed
+    pub fn incr_expired_keys(&self, n: u64) {
+        self.expired_keys.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn record_expire_cycle(&self, sampled: u64, expired: u64) {
+        self.incr_expired_keys(expired);
+        let current = if sampled == 0 {
+            0.0
+        } else {
+            expired as f64 / sampled as f64
+        };
+        let old = f64::from_bits(self.expired_stale_perc.load(Ordering::Relaxed));
+        let perc = current * 0.05 + old * 0.95;
+        self.expired_stale_perc
+            .store(perc.to_bits(), Ordering::Relaxed);
+    }
+
+    fn to_info(&self) -> DashMap<String, String> {
+        let section = DashMap::new();
+        section.insert(
+            "expired_keys".to_string(),
+            self.expired_keys.load(Ordering::Relaxed).to_string(),
+        );
+        section.insert(
+            "expired_stale_perc".to_string(),
+            format!(
+                "{:.2}",
+                f64::from_bits(self.expired_stale_perc.load(Ordering::Relaxed)) * 100.0
+            ),
+        );
+        section
+    }
+}
+
+// how SHUTDOWN or a signal asked the server to stop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShutdownMode {
+    // save only when save rules are configured
+    Default,
+    // SHUTDOWN SAVE
+    Save,
+    // SHUTDOWN NOSAVE
+    NoSave,
+}
+
 #[derive(Clone, Debug)]
 pub struct Server {
     pub storage: Arc<Mutex<RdbFile>>,
     pub option: ServerOpt,
     pub repl_set: Arc<Mutex<ReplicationSet>>,
+    pub stats: Arc<ServerStats>,
+    // shared with storage, every expiry decision reads the time from here
+    pub clock: Arc<dyn Clock>,
+    // toggled by DEBUG SET-ACTIVE-EXPIRE
+    pub active_expire: Arc<AtomicBool>,
+    // changed by CONFIG SET proto-max-bulk-len, read by every connection's decoder
+    pub proto_max_bulk_len: Arc<AtomicU64>,
+    pub persistence: Arc<PersistenceState>,
+    pub aof: Arc<Aof>,
+    // set once when a shutdown is requested, watched by the accept loop and every connection
+    shutdown: Arc<watch::Sender<Option<ShutdownMode>>>,
     info: Arc<Mutex<DashMap<String, DashMap<String, String>>>>,
 }
 
 impl Server {
     pub async fn new(conf: ServerOpt) -> Result<Self> {
+        Server::with_clock(conf, Arc::new(SystemClock::new())).await
+    }
+
+    pub async fn with_clock(conf: ServerOpt, clock: Arc<dyn Clock>) -> Result<Self> {
         let mut server: Server;
 
-        let mut file_path = PathBuf::from(conf.db_conf.get_dir());
-        if conf.db_conf.get_dir() != "" && !file_path.exists() {
-            fs::create_dir(file_path.as_path()).expect("creat redis dir error");
-        }
-        if conf.db_conf.get_db_filename() != "" {
-            file_path.push(conf.db_conf.get_db_filename());
+        let dir = PathBuf::from(conf.db_conf.get_dir());
+        if conf.db_conf.get_dir() != "" && !dir.exists() {
+            fs::create_dir(dir.as_path()).expect("creat redis dir error");
         }
+        // the file SAVE, BGSAVE and the save rules write, ./dump.rdb by default
+        let file_path = persistence::rdb_path(&conf.db_conf);
 
         let ser_info: DashMap<String, DashMap<String, String>> = DashMap::new();
 
@@ -102,96 +255,160 @@ impl Server {
 
         log::debug!("server info is {:?}", ser_info);
 
+        // with appendonly the append-only file has the latest data, the rdb file is not loaded
+        let load_aof = conf.appendonly && aof::exists(&conf);
+
         //parse storage file
-        let storage = if file_path.is_file() {
-            let mut rdbfile_reader = RdbParser::new(File::open(file_path.as_path()).await?);
-            Arc::new(Mutex::new(
-                rdbfile_reader.parse().await.expect("rdb_file parse error"),
-            ))
+        let mut rdb_file = if file_path.is_file() && !load_aof {
+            let mut rdbfile_reader = RdbParser::new(File::open(file_path.as_path()).await?)
+                .skip_checksum(conf.rdb_skip_checksum)
+                .max_bulk_len(conf.proto_max_bulk_len);
+            let mut rdb_file = rdbfile_reader
+                .parse()
+                .await
+                .with_context(|| format!("can't load the rdb file {}", file_path.display()))?;
+            // the keyspace is saved in the current format whatever version it was loaded from
+            rdb_file.version = RDB_VERSION;
+            if let Some(db) = rdb_file
+                .databases
+                .iter()
+                .map(|e| *e.key())
+                .find(|db| *db >= conf.databases)
+            {
+                bail!(
+                    "the rdb file has database {} but the server is configured with {} databases",
+                    db,
+                    conf.databases
+                );
+            }
+            rdb_file
         } else {
-            Arc::new(Mutex::new(RdbFile::new(RDB_VERSION)))
+            RdbFile::new(RDB_VERSION)
         };
+        rdb_file.set_clock(clock.clone());
+        let storage = Arc::new(Mutex::new(rdb_file));
+
+        let persistence = Arc::new(PersistenceState::new(
+            clock.now_millis() / 1000,
+            conf.save_params.clone(),
+            conf.rdb_compression,
+        ));
+        let proto_max_bulk_len = Arc::new(AtomicU64::new(conf.proto_max_bulk_len));
+        let aof = Arc::new(Aof::new(&conf));
 
         server = Server {
             storage: storage,
             option: conf,
             repl_set: Arc::new(Mutex::new(ReplicationSet::new())),
+            stats: Arc::new(ServerStats::default()),
+            clock: clock.clone(),
+            active_expire: Arc::new(AtomicBool::new(true)),
+            proto_max_bulk_len: proto_max_bulk_len,
+            persistence: persistence,
+            aof: aof,
+            shutdown: Arc::new(watch::Sender::new(None)),
             info: Arc::new(Mutex::new(ser_info)),
         };
 
+        if load_aof {
+            aof::load(&mut server).await?;
+        }
+        if server.aof.enabled() {
+            aof::open(&server).await?;
+        }
+
         server.init().await;
 
         Ok(server)
     }
     pub async fn init(&mut self) {
+        // a replica leaves expiring keys to its master
+        if self.is_mater() {
+            tokio::spawn(expire::run(self.clone()));
+        }
+        tokio::spawn(persistence::run(self.clone()));
+        tokio::spawn(aof::run(self.clone()));
+
+        // SIGTERM / SIGINT go through the same path as SHUTDOWN
+        let server = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_signals(server).await {
+                error!("can't listen for signals: {}", e);
+            }
+        });
         log::info!("server init has finished!!");
     }
     pub async fn start(&mut self, listener: TcpListener) -> Result<()> {
+        let mut shutdown = self.shutdown.subscribe();
         if self.is_slave() {
             let (addr, port) = self.option.replicaof.as_ref().unwrap();
             let mut stream = TcpStream::connect(format!("{}:{}", addr, port))
                 .await
                 .expect("connect master failed!!");
 
-            self.ping_master(&mut stream)
+            // the handshake replies, the rdb payload and the command stream all share one buffer
+            let mut decoder = RespDecoder::new();
+            decoder.set_max_bulk_len(self.proto_max_bulk_len.load(Ordering::Relaxed));
+            self.ping_master(&mut stream, &mut decoder)
                 .await
                 .expect("ping master failed!");
-            self.repl_conf(&mut stream)
+            self.repl_conf(&mut stream, &mut decoder)
                 .await
                 .expect("repl conf failed!");
-            self.psync(&mut stream).await.expect("psync failed!");
+            self.psync(&mut stream, &mut decoder)
+                .await
+                .expect("psync failed!");
 
-            let stream_arc = Arc::new(RwLock::new(stream));
+            let stream_arc = Arc::new(Mutex::new(stream));
+            let mut client = ClientState::default();
 
-            let mut buf: [u8; BUF_SIZE] = [0; BUF_SIZE];
+            // commands propagated by the master are applied without sending replies back
             loop {
-                let n = {
-                    let guard = stream_arc.read().await;
-                    if guard.readable().await.is_ok() {
-                        guard.try_read(&mut buf)?
-                    } else {
-                        0
+                while let Some(frame) = decoder.decode()? {
+                    let args = frame.into_args()?;
+                    if args.is_empty() {
+                        continue;
                     }
-                };
+                    log::debug!("read from master: {:?}", args);
+                    if let Err(e) = commands::from_cmd_to_exec(
+                        args,
+                        Some(stream_arc.clone()),
+                        self,
+                        &mut client,
+                    )
+                    .await
+                    {
+                        error!("apply command from master error: {}", e);
+                    }
+                }
 
+                let n = tokio::select! {
+                    n = async { stream_arc.lock().await.read_buf(decoder.buffer_mut()).await } => n?,
+                    _ = wait_shutdown(&mut shutdown) => break,
+                };
                 if n == 0 {
-                    break;
+                    return Ok(());
                 }
-                let read_array: Array =
-                    Array::parse(&buf, &mut 0, &n).expect("bulkString parse error");
-                // log::debug!("read from stream is {:?}", read_array);
-
-                let r = read_array.to_vec();
-                // log::debug!("read from stream is {:?}", &r);
-                let read_slice: Vec<&[u8]> = r
-                    .split(|c| *c == b'\r' || *c == b'\n')
-                    .filter(|s| !s.is_empty())
-                    .collect();
-
-                log::debug!("read from stream  slice is {:?}", read_slice);
-
-                let (f, l) = read_slice.split_first().expect("parse command error !");
-
-                let arg_len: u8 = String::from_utf8_lossy(&f[1..])
-                    .parse()
-                    .expect("parse arg_len error");
-
-                log::debug!("arg len:is {}", arg_len);
-
-                commands::from_cmd_to_exec(l.to_vec(), arg_len, stream_arc.clone(), self).await?;
             }
-            Ok(())
+            self.finish_shutdown().await
         } else {
+            // every connection task holds a sender, recv() returns None once they all finished
+            let (in_flight_tx, mut in_flight_rx) = mpsc::channel::<()>(1);
             loop {
-                let stream = listener.accept().await;
+                let stream = tokio::select! {
+                    stream = listener.accept() => stream,
+                    _ = wait_shutdown(&mut shutdown) => break,
+                };
                 match stream {
                     Ok((stream, _)) => {
                         let mut server_clone = self.clone();
                         let stream_arc = Arc::new(Mutex::new(stream));
+                        let in_flight = in_flight_tx.clone();
                         tokio::spawn(async move {
                             if let Err(e) = server_clone.handle_client(stream_arc.clone()).await {
                                 error!("handle client error :{}", e);
                             }
+                            drop(in_flight);
                         });
                     }
                     Err(e) => {
@@ -199,23 +416,127 @@ impl Server {
                     }
                 }
             }
+
+            // stop accepting, then let the connections finish the commands they already read
+            drop(listener);
+            drop(in_flight_tx);
+            info!("waiting for client connections to finish...");
+            let _ = in_flight_rx.recv().await;
+            self.finish_shutdown().await
+        }
+    }
+
+    // SHUTDOWN and SIGTERM/SIGINT: save first, and only when that worked stop the accept
+    // loop and the connections. A failed save cancels the shutdown, the server keeps
+    // serving and the error is returned to SHUTDOWN.
+    pub async fn shutdown(&self, mode: ShutdownMode) -> Result<()> {
+        // no write runs from here on, they are refused once the shutdown is requested,
+        // so the snapshot has every write a client was told about
+        let _writes = self.aof.write_lock.lock().await;
+        if self.is_shutting_down() {
+            return Ok(());
+        }
+        let save = match mode {
+            ShutdownMode::Save => true,
+            ShutdownMode::NoSave => false,
+            ShutdownMode::Default => !self.persistence.save_params().is_empty(),
+        };
+        if save {
+            info!("Saving the final RDB snapshot before exiting.");
+            persistence::wait_bgsave(self).await;
+            if let Err(e) = persistence::save(self).await {
+                error!("Error trying to save the DB, can't exit: {}", e);
+                error!("Errors trying to SHUTDOWN. Check logs.");
+                bail!(RedisError::Other(
+                    "Errors trying to SHUTDOWN. Check logs.".to_string()
+                ));
+            }
+        }
+        self.request_shutdown(mode);
+        Ok(())
+    }
+
+    // ask the accept loop and all connections to stop, only the first request counts
+    fn request_shutdown(&self, mode: ShutdownMode) {
+        self.shutdown.send_if_modified(|m| {
+            if m.is_some() {
+                return false;
+            }
+            *m = Some(mode);
+            true
+        });
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.borrow().is_some()
+    }
+
+    // last steps once no command is running any more, the RDB was saved by `shutdown`
+    async fn finish_shutdown(&self) -> Result<()> {
+        if let Err(e) = aof::sync(self).await {
+            error!("error fsyncing the append only file: {}", e);
         }
+
+        // whatever was propagated to replicas should reach them before the sockets close
+        for r in self.repl_set.lock().await.get_repls() {
+            let mut stream = r.stream.lock().await;
+            if let Err(e) = stream.flush().await {
+                error!("flush replica stream error: {}", e);
+            }
+            let _ = stream.shutdown().await;
+        }
+        info!("Redis is now ready to exit, bye bye...");
+        Ok(())
     }
-    pub async fn get_a_info(&self, k: &str) -> DashMap<String, String> {
-        self.info
-            .lock()
-            .await
-            .get(k)
-            .expect("get server info error")
-            // .value()
-            .clone()
+
+    pub async fn get_a_info(&self, k: &str) -> Option<DashMap<String, String>> {
+        self.refresh_info().await;
+        self.info.lock().await.get(k).map(|v| v.value().clone())
     }
 
     pub async fn get_all_info(&self) -> DashMap<String, DashMap<String, String>> {
+        self.refresh_info().await;
         self.info.lock().await.to_owned()
     }
 
-    pub async fn ping_master(&self, stream: &mut TcpStream) -> Result<()> {
+    // sections built from live counters
+    async fn refresh_info(&self) {
+        let keyspace: DashMap<String, String> = DashMap::new();
+        let dirty;
+        {
+            let storage = self.storage.lock().await;
+            dirty = storage.dirty();
+            for e in storage.databases.iter() {
+                let db = *e.key();
+                if e.value().is_empty() {
+                    continue;
+                }
+                keyspace.insert(
+                    format!("db{}", db),
+                    format!(
+                        "keys={},expires={},avg_ttl={}",
+                        e.value().len(),
+                        storage.expires_size(db),
+                        storage.avg_ttl(db)
+                    ),
+                );
+            }
+        }
+        let info = self.info.lock().await;
+        info.insert("stats".to_string(), self.stats.to_info());
+        let persistence = self.persistence.to_info(dirty);
+        for (k, v) in self.aof.to_info(self.clock.now_millis()) {
+            persistence.insert(k, v);
+        }
+        info.insert("persistence".to_string(), persistence);
+        info.insert("keyspace".to_string(), keyspace);
+    }
+
+    pub async fn ping_master(
+        &self,
+        stream: &mut TcpStream,
+        decoder: &mut RespDecoder,
+    ) -> Result<()> {
         let respon_byte = ArrayBuilder::new()
             .insert(resp_protocol::RespType::BulkString(BulkString::new(
                 b"PING",
@@ -225,10 +546,10 @@ impl Server {
         stream.write_all(&respon_byte.to_vec()).await?;
         stream.flush().await?;
 
-        Server::get_repspon_master(stream, b"PONG").await
+        Server::get_repspon_master(stream, decoder, b"PONG").await
     }
 
-    pub async fn repl_conf(&self, stream: &mut TcpStream) -> Result<()> {
+    pub async fn repl_conf(&self, stream: &mut TcpStream, decoder: &mut RespDecoder) -> Result<()> {
         let mut listen_port = ArrayBuilder::new();
 
         listen_port.insert(resp_protocol::RespType::BulkString(BulkString::new(
@@ -260,10 +581,12 @@ impl Server {
         stream.write_all(&psync.build().to_vec()).await?;
         stream.flush().await?;
 
-        Server::get_repspon_master(stream, b"OK").await
+        // one reply for each REPLCONF
+        Server::get_repspon_master(stream, decoder, b"OK").await?;
+        Server::get_repspon_master(stream, decoder, b"OK").await
     }
 
-    pub async fn psync(&self, stream: &mut TcpStream) -> Result<()> {
+    pub async fn psync(&self, stream: &mut TcpStream, decoder: &mut RespDecoder) -> Result<()> {
         let mut psync = ArrayBuilder::new();
 
         psync.insert(resp_protocol::RespType::BulkString(BulkString::new(
@@ -277,23 +600,42 @@ impl Server {
         stream.write_all(&psync.build().to_vec()).await?;
         stream.flush().await?;
 
-        Server::get_repspon_master(stream, b"OK").await
-    }
-    async fn get_repspon_master(stream: &mut TcpStream, expect: &[u8]) -> Result<()> {
-        let mut buf: [u8; BUF_SIZE] = [0; BUF_SIZE];
-        let n = stream.read(&mut buf).await?;
+        Server::get_repspon_master(stream, decoder, b"FULLRESYNC").await?;
+
+        // the master follows "+FULLRESYNC <replid> <offset>" with its rdb file
+        let rdb = loop {
+            if let Some(rdb) = decoder.decode_rdb()? {
+                break rdb;
+            }
+            if stream.read_buf(decoder.buffer_mut()).await? == 0 {
+                bail!("master closed the connection during full resync");
+            }
+        };
         log::debug!(
-            "[Master Response!] read from stream bytes num is  {}\t to string is {}",
-            n,
-            String::from_utf8_lossy(&buf[0..n]).to_string()
+            "[Master Response!] received rdb payload of {} bytes",
+            rdb.len()
         );
-        let read_response =
-            SimpleString::parse(&buf, &mut 0, &buf.len()).expect("simple string parse error!");
-        if read_response != SimpleString::new(expect) {
-            bail!("Cant't receive correct info from master")
-        }
         Ok(())
     }
+    async fn get_repspon_master(
+        stream: &mut TcpStream,
+        decoder: &mut RespDecoder,
+        expect: &[u8],
+    ) -> Result<()> {
+        let frame = loop {
+            if let Some(frame) = decoder.decode()? {
+                break frame;
+            }
+            if stream.read_buf(decoder.buffer_mut()).await? == 0 {
+                bail!("master closed the connection");
+            }
+        };
+        log::debug!("[Master Response!] read from stream is {:?}", frame);
+        match frame {
+            Frame::Simple(s) if s.starts_with(expect) => Ok(()),
+            _ => bail!("Cant't receive correct info from master"),
+        }
+    }
 
     pub async fn insert_a_repl(&mut self, a_repl: Replication) {
         self.repl_set.lock().await.add_a_repl(a_repl);
@@ -319,56 +661,67 @@ impl Server {
     }
 
     pub async fn handle_client(&mut self, stream_arc: Arc<Mutex<TcpStream>>) -> Result<()> {
-        let mut buf: [u8; BUF_SIZE] = [0; BUF_SIZE];
+        let mut decoder = RespDecoder::new();
+        let mut client = ClientState::default();
+        let mut shutdown = self.shutdown.subscribe();
         loop {
-            let n = {
-                let mut stream = stream_arc.try_lock()?;
-                let ready = stream.ready(Interest::READABLE).await?;
-                if !ready.is_readable() {
-                    info!("[connection can't read or write! A client connection CLOSED !] !");
-                    break;
+            // answer every complete command already buffered before reading again,
+            // so pipelined requests are replied to in order
+            decoder.set_max_bulk_len(self.proto_max_bulk_len.load(Ordering::Relaxed));
+            loop {
+                // a malformed request can't be resynchronized: report it and close the connection
+                let args = match decoder
+                    .decode()
+                    .and_then(|f| f.map(Frame::into_args).transpose())
+                {
+                    Ok(Some(args)) => args,
+                    Ok(None) => break,
+                    Err(e) => {
+                        let mut stream = stream_arc.lock().await;
+                        stream.write_all(&error_reply(&e)).await?;
+                        stream.flush().await?;
+                        bail!("{e}");
+                    }
+                };
+                if args.is_empty() {
+                    continue;
                 }
-                stream.read(&mut buf).await?
-            };
-            if n == 0 {
-                break;
+                log::debug!("read from stream args is {:?}", args);
+
+                // command errors are replied to and the connection stays open
+                let out = match commands::from_cmd_to_exec(
+                    args,
+                    Some(stream_arc.clone()),
+                    self,
+                    &mut client,
+                )
+                .await
+                {
+                    Ok(out) => out,
+                    Err(e) => {
+                        log::debug!("command error: {}", e);
+                        error_reply(&e)
+                    }
+                };
+                let mut stream = stream_arc.lock().await;
+                stream.writable().await?;
+                stream.write_all(&out).await?;
+                log::debug!(
+                    "output is ready to write back:{:?}",
+                    String::from_utf8_lossy(&out)
+                );
+                stream.flush().await?;
             }
 
-            let read_array: Array = Array::parse(&buf, &mut 0, &n).expect("bulkString parse error");
-            // log::debug!("read from stream is {:?}", read_array);
-
-            let r = read_array.to_vec();
-            // log::debug!("read from stream is {:?}", &r);
-            let read_slice: Vec<&[u8]> = r
-                .split(|c| *c == b'\r' || *c == b'\n')
-                .filter(|s| !s.is_empty())
-                .collect();
-
-            log::debug!("read from stream  slice is {:?}", read_slice);
-
-            let (f, l) = read_slice.split_first().expect("parse command error !");
-
-            let arg_len: u8 = String::from_utf8_lossy(&f[1..])
-                .parse()
-                .expect("parse arg_len error");
-
-            log::debug!("arg len:is {}", arg_len);
-
-            let output =
-                commands::from_cmd_to_exec(l.to_vec(), arg_len, stream_arc.clone(), self).await;
-            match output {
-                Ok(out) => {
-                    let mut stream = stream_arc.lock().await;
-                    stream.writable().await?;
-                    // log::debug!("output is ready to write back Vec:{:?}", &out);
-                    stream.write_all(&out).await?;
-                    log::debug!(
-                        "output is ready to write back:{:?}",
-                        String::from_utf8_lossy(&out)
-                    );
-                    stream.flush().await?;
+            let n = tokio::select! {
+                n = async { stream_arc.lock().await.read_buf(decoder.buffer_mut()).await } => n?,
+                _ = wait_shutdown(&mut shutdown) => break,
+            };
+            if n == 0 {
+                if !decoder.is_empty() {
+                    info!("[client closed the connection in the middle of a command]");
                 }
-                Err(e) => bail!("{e}"),
+                break;
             }
         }
 
@@ -381,3 +734,28 @@ impl Server {
         self.option.is_master
     }
 }
+
+// resolves once a shutdown has been requested
+async fn wait_shutdown(rx: &mut watch::Receiver<Option<ShutdownMode>>) {
+    let _ = rx.wait_for(|m| m.is_some()).await;
+}
+
+// Every signal asks for a shutdown: when one was cancelled because the save failed the
+// handlers stay installed, so the next SIGTERM / SIGINT has to be tried again.
+async fn handle_signals(server: Server) -> Result<()> {
+    let mut term = signal(SignalKind::terminate())?;
+    let mut int = signal(SignalKind::interrupt())?;
+    loop {
+        let name = tokio::select! {
+            _ = term.recv() => "SIGTERM",
+            _ = int.recv() => "SIGINT",
+        };
+        info!("Received {} scheduling shutdown...", name);
+        if server.shutdown(ShutdownMode::Default).await.is_err() {
+            error!(
+                "{} received but errors trying to shut down the server, check the logs for more information",
+                name
+            );
+        }
+    }
+}
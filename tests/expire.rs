@@ -0,0 +1,195 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use bytes::Bytes;
+
+use codecrafters_redis::{
+    clock::{Clock, MockClock},
+    commands::{self, ClientState},
+    db::{Dbconf, Expiry, KeyValue, RdbFile, RedisValue, RDB_VERSION},
+    expire, persistence,
+    server::{Server, ServerOpt},
+};
+
+// Expiry driven by a MockClock: nothing here sleeps, time only moves when a test advances it.
+
+const START: u64 = 1_700_000_000_000;
+
+// an empty directory of its own for every test
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rust-redis-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn server_with_clock(dir: &Path, clock: Arc<MockClock>) -> Server {
+    let mut db_conf = Dbconf::new();
+    db_conf.set(dir.display().to_string(), "dump.rdb".to_string());
+    let opt = ServerOpt::new("0".to_string(), db_conf, None, vec![], true);
+    Server::with_clock(opt, clock).await.unwrap()
+}
+
+async fn exec(server: &mut Server, args: &[&str]) -> Vec<u8> {
+    let args = args.iter().map(|a| Bytes::from(a.to_string())).collect();
+    commands::from_cmd_to_exec(args, None, server, &mut ClientState::default())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn lazy_expiry() {
+    let dir = test_dir("lazy-expiry");
+    let clock = Arc::new(MockClock::new(START));
+    let mut server = server_with_clock(&dir, clock.clone()).await;
+
+    exec(&mut server, &["SET", "k", "v", "PX", "1500"]).await;
+    assert_eq!(exec(&mut server, &["TTL", "k"]).await, b":2\r\n");
+    assert_eq!(exec(&mut server, &["PTTL", "k"]).await, b":1500\r\n");
+
+    clock.advance(Duration::from_millis(1499));
+    assert_eq!(exec(&mut server, &["GET", "k"]).await, b"$1\r\nv\r\n");
+    assert_eq!(exec(&mut server, &["PTTL", "k"]).await, b":1\r\n");
+
+    // like Redis, a key is still there at its deadline and gone right after it
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(exec(&mut server, &["GET", "k"]).await, b"$1\r\nv\r\n");
+    assert_eq!(exec(&mut server, &["PTTL", "k"]).await, b":0\r\n");
+
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(exec(&mut server, &["GET", "k"]).await, b"$-1\r\n");
+    assert_eq!(exec(&mut server, &["TTL", "k"]).await, b":-2\r\n");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn debug_sleep_advances_the_clock() {
+    let dir = test_dir("debug-sleep");
+    let clock = Arc::new(MockClock::new(START));
+    let mut server = server_with_clock(&dir, clock.clone()).await;
+
+    exec(&mut server, &["SET", "k", "v", "PX", "1500"]).await;
+    assert_eq!(
+        exec(&mut server, &["DEBUG", "SLEEP", "1.5"]).await,
+        b"+OK\r\n"
+    );
+    assert_eq!(clock.now_millis(), START + 1500);
+    assert_eq!(exec(&mut server, &["PTTL", "k"]).await, b":0\r\n");
+    exec(&mut server, &["DEBUG", "SLEEP", "0.001"]).await;
+    assert_eq!(exec(&mut server, &["GET", "k"]).await, b"$-1\r\n");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn set_rejects_deadlines_past_i64() {
+    let dir = test_dir("set-overflow");
+    let clock = Arc::new(MockClock::new(START));
+    let mut server = server_with_clock(&dir, clock.clone()).await;
+
+    let args = ["SET", "k", "v", "PX", "9223372036854775807"];
+    let args = args.iter().map(|a| Bytes::from(a.to_string())).collect();
+    let err = commands::from_cmd_to_exec(args, None, &mut server, &mut ClientState::default())
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "ERR invalid expire time in 'set' command");
+    assert_eq!(exec(&mut server, &["GET", "k"]).await, b"$-1\r\n");
+
+    // the largest deadline that still fits is accepted
+    let px = (i64::MAX as u64 - START).to_string();
+    assert_eq!(
+        exec(&mut server, &["SET", "k", "v", "PX", &px]).await,
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        exec(&mut server, &["PTTL", "k"]).await,
+        format!(":{}\r\n", px).into_bytes()
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn active_expiry() {
+    let dir = test_dir("active-expiry");
+    let clock = Arc::new(MockClock::new(START));
+    let mut server = server_with_clock(&dir, clock.clone()).await;
+    // the cycles are run by hand below
+    exec(&mut server, &["DEBUG", "SET-ACTIVE-EXPIRE", "0"]).await;
+
+    for i in 0..10 {
+        exec(
+            &mut server,
+            &["SET", &format!("short{}", i), "v", "EX", "10"],
+        )
+        .await;
+        exec(
+            &mut server,
+            &["SET", &format!("long{}", i), "v", "EX", "100"],
+        )
+        .await;
+    }
+    exec(&mut server, &["SET", "persistent", "v"]).await;
+    assert_eq!(server.storage.lock().await.dbsize(0).await, 21);
+
+    // nothing is due yet
+    expire::active_expire_cycle(&server).await;
+    assert_eq!(server.storage.lock().await.dbsize(0).await, 21);
+
+    // the keys are removed without ever being accessed
+    clock.advance(Duration::from_millis(10_001));
+    expire::active_expire_cycle(&server).await;
+    assert_eq!(server.storage.lock().await.dbsize(0).await, 11);
+
+    clock.advance(Duration::from_secs(90));
+    expire::active_expire_cycle(&server).await;
+    assert_eq!(server.storage.lock().await.dbsize(0).await, 1);
+    assert_eq!(
+        exec(&mut server, &["GET", "persistent"]).await,
+        b"$1\r\nv\r\n"
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn rdb_loaded_expiry() {
+    let dir = test_dir("rdb-expiry");
+    let mut rdb_file = RdbFile::new(RDB_VERSION);
+    let keys = [
+        ("expired", Some(START - 1000)),
+        ("later", Some(START + 5000)),
+        ("persistent", None),
+    ];
+    for (key, expire_at) in keys {
+        let kv = KeyValue {
+            value: RedisValue::String(Bytes::from_static(b"v")),
+            expiry: expire_at.map(Expiry::from_millis),
+        };
+        rdb_file.insert_or_replace(0, Bytes::from(key), kv).await;
+    }
+    persistence::write_rdb(&rdb_file, &dir.join("dump.rdb"), true)
+        .await
+        .unwrap();
+
+    let clock = Arc::new(MockClock::new(START));
+    let mut server = server_with_clock(&dir, clock.clone()).await;
+
+    // the deadlines in the file are compared with the injected clock, not the wall clock
+    assert_eq!(exec(&mut server, &["GET", "expired"]).await, b"$-1\r\n");
+    assert_eq!(exec(&mut server, &["PTTL", "later"]).await, b":5000\r\n");
+    assert_eq!(exec(&mut server, &["TTL", "persistent"]).await, b":-1\r\n");
+
+    clock.advance(Duration::from_millis(5001));
+    assert_eq!(exec(&mut server, &["GET", "later"]).await, b"$-1\r\n");
+    assert_eq!(
+        exec(&mut server, &["GET", "persistent"]).await,
+        b"$1\r\nv\r\n"
+    );
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,147 @@
+use std::io::Cursor;
+
+use codecrafters_redis::{
+    crc64::Digest,
+    db::{ChecksumStatus, RdbParser, RedisValue},
+};
+
+// A dump laid out the way a Redis 7.2 server writes one with a function library loaded,
+// a stream with a consumer group, an LRU idle time before a key and module aux data.
+// None of these are supported: they are skipped and the other keys still load.
+
+fn len(n: u64) -> Vec<u8> {
+    match n {
+        0..=63 => vec![n as u8],
+        64..=16383 => vec![0x40 | (n >> 8) as u8, n as u8],
+        _ if n <= u32::MAX as u64 => [vec![0x80], (n as u32).to_be_bytes().to_vec()].concat(),
+        _ => [vec![0x81], n.to_be_bytes().to_vec()].concat(),
+    }
+}
+
+fn string(s: &[u8]) -> Vec<u8> {
+    [len(s.len() as u64), s.to_vec()].concat()
+}
+
+fn stream_id(ms: u64, seq: u64) -> Vec<u8> {
+    [ms.to_be_bytes(), seq.to_be_bytes()].concat()
+}
+
+// the id RedisModule_CreateDataType gives a type name and encoding version
+fn module_id(name: &str, encver: u64) -> u64 {
+    const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let id = name.bytes().fold(0u64, |id, c| {
+        (id << 6) | CHARSET.iter().position(|&x| x == c).unwrap() as u64
+    });
+    (id << 10) | encver
+}
+
+fn redis_7_2_dump() -> Vec<u8> {
+    let mut d = b"REDIS0011".to_vec();
+    d.extend([0xFA]);
+    d.extend(string(b"redis-ver"));
+    d.extend(string(b"7.2.4"));
+    d.extend([0xFA]);
+    d.extend(string(b"redis-bits"));
+    d.extend([0xC0, 64]);
+
+    // FUNCTION2: the library source
+    d.extend([0xF5]);
+    d.extend(string(
+        b"#!lua name=mylib\nredis.register_function('myfunc', function() return 1 end)",
+    ));
+
+    d.extend([0xFE, 0, 0xFB, 4, 1]);
+
+    // plain string
+    d.extend([0]);
+    d.extend(string(b"plain"));
+    d.extend(string(b"v"));
+
+    // LRU idle time (maxmemory-policy allkeys-lru) before the value type
+    d.extend([0xF8]);
+    d.extend(len(3600));
+    d.extend([0]);
+    d.extend(string(b"idle"));
+    d.extend(string(b"v"));
+
+    // STREAM_LISTPACKS_3 with one entry, one group, one pending entry and one consumer
+    let ms = 1_700_000_000_000;
+    d.extend([21]);
+    d.extend(string(b"events"));
+    d.extend(len(1));
+    d.extend(string(&stream_id(ms, 0)));
+    d.extend(string(&[
+        0x1f, 0, 0, 0, 0x09, 0, 0x01, 0x01, 0x00, 0x01, 0x01, 0x01, 0x81, 0x66, 0x02, 0x00, 0x01,
+        0x02, 0x01, 0x00, 0x01, 0x00, 0x01, 0x81, 0x76, 0x02, 0x05, 0x01, 0xff,
+    ]));
+    // length, last id, first id, max deleted id, entries added
+    for n in [1, ms, 0, ms, 0, 0, 0, 1] {
+        d.extend(len(n));
+    }
+    d.extend(len(1));
+    d.extend(string(b"grp"));
+    d.extend(len(ms));
+    d.extend(len(0));
+    // entries read
+    d.extend(len(1));
+    d.extend(len(1));
+    d.extend(stream_id(ms, 0));
+    d.extend((ms + 5).to_le_bytes());
+    d.extend(len(1));
+    d.extend(len(1));
+    d.extend(string(b"alice"));
+    d.extend((ms + 5).to_le_bytes());
+    d.extend((ms + 5).to_le_bytes());
+    d.extend(len(1));
+    d.extend(stream_id(ms, 0));
+
+    // a key after the stream, with an expire
+    d.extend([0xFC]);
+    d.extend((ms + 60_000).to_le_bytes());
+    d.extend([0]);
+    d.extend(string(b"after"));
+    d.extend(string(b"v"));
+
+    // MODULE_AUX written after the keyspace (when = REDISMODULE_AUX_AFTER_RDB)
+    d.extend([0xF7]);
+    d.extend(len(module_id("ReJSON-RL", 3)));
+    d.extend([2, 2]);
+    d.extend([2]);
+    d.extend(len(7));
+    d.extend([4]);
+    d.extend(1.5f64.to_le_bytes());
+    d.extend([5]);
+    d.extend(string(b"data"));
+    d.extend([0]);
+
+    d.extend([0xFF]);
+    let mut crc = Digest::new();
+    crc.write(&d);
+    d.extend(crc.sum64().to_le_bytes());
+    d
+}
+
+#[tokio::test]
+async fn skips_streams_functions_and_module_aux() {
+    let data = redis_7_2_dump();
+    let mut parser = RdbParser::new(Cursor::new(data.as_slice()));
+    let rdb_file = parser.parse().await.unwrap();
+    assert!(matches!(parser.checksum(), ChecksumStatus::Ok(_)));
+    assert_eq!(parser.into_inner().position() as usize, data.len());
+
+    let db = rdb_file.databases.get(&0).unwrap();
+    let mut keys: Vec<_> = db.iter().map(|e| e.key().clone()).collect();
+    keys.sort();
+    assert_eq!(keys, ["after", "idle", "plain"]);
+    for key in keys {
+        assert!(matches!(&db.get(&key).unwrap().value, RedisValue::String(v) if v == "v"));
+    }
+    assert_eq!(
+        db.get(b"after".as_slice())
+            .unwrap()
+            .expiry
+            .unwrap()
+            .as_millis(),
+        1_700_000_060_000
+    );
+}